@@ -1,8 +1,10 @@
 mod utils;
 use std::io::Read;
 
+use rust_gameboy_core::gameboy::ChannelState as ChannelState_;
 use rust_gameboy_core::gameboy::GameBoy as GameBoy_;
-use rust_gameboy_core::gameboy::{HEIGHT, WIDTH};
+use rust_gameboy_core::gameboy::Lockup as Lockup_;
+use rust_gameboy_core::gameboy::{DmgPalette, Model, HEIGHT, WIDTH};
 use rust_gameboy_core::joypad::JoyPadKey as JoyPadKey_;
 use wasm_bindgen::prelude::*;
 
@@ -39,12 +41,63 @@ impl From<JoyPadKey> for JoyPadKey_ {
     }
 }
 
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub struct ChannelState {
+    pub enable: bool,
+    pub dac_enable: bool,
+    pub frequency: u16,
+    pub frequency_hz: f32,
+    pub volume: u8,
+    pub duty: u8,
+    pub envelope_increase: bool,
+    pub envelope_period: u8,
+    pub output: u8,
+    pub muted: bool,
+    pub solo: bool,
+}
+impl From<ChannelState_> for ChannelState {
+    fn from(state: ChannelState_) -> Self {
+        Self {
+            enable: state.enable,
+            dac_enable: state.dac_enable,
+            frequency: state.frequency,
+            frequency_hz: state.frequency_hz,
+            volume: state.volume,
+            duty: state.duty,
+            envelope_increase: state.envelope_increase,
+            envelope_period: state.envelope_period,
+            output: state.output,
+            muted: state.muted,
+            solo: state.solo,
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Lockup {
+    pub opcode: u8,
+    pub address: u16,
+}
+impl From<Lockup_> for Lockup {
+    fn from(lockup: Lockup_) -> Self {
+        Self {
+            opcode: lockup.opcode,
+            address: lockup.address,
+        }
+    }
+}
+
 #[wasm_bindgen]
 pub struct GameBoy {
     bios: Vec<u8>,
     rom: Vec<u8>,
     inner: Option<GameBoy_>,
     status: Option<Vec<u8>>,
+    sample_rate: u32,
+    palette: DmgPalette,
+    model: Option<Model>,
 }
 
 #[wasm_bindgen]
@@ -57,6 +110,9 @@ impl GameBoy {
             rom: vec![],
             inner: None,
             status: None,
+            sample_rate: 0,
+            palette: DmgPalette::default(),
+            model: None,
         })
     }
 
@@ -70,7 +126,12 @@ impl GameBoy {
 
     pub fn start(&mut self) {
         let cartridge = GameBoy_::get_cartridge(self.rom.clone());
-        let inner = GameBoy_::new(self.bios.clone(), cartridge);
+        let mut inner = match self.model {
+            Some(model) => GameBoy_::with_model(model, self.bios.clone(), cartridge),
+            None => GameBoy_::new(self.bios.clone(), cartridge),
+        };
+        inner.set_sample_rate(self.sample_rate);
+        inner.set_dmg_palette(self.palette);
         self.inner = Some(inner);
     }
 
@@ -84,6 +145,124 @@ impl GameBoy {
         }
     }
 
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        if let Some(gameboy) = self.inner.as_mut() {
+            gameboy.set_sample_rate(sample_rate);
+        }
+    }
+
+    // green, grayscale, pocket, or 4 comma separated RRGGBB colors, 12 for separate bg, obj0
+    // and obj1 palettes
+    pub fn set_dmg_palette(&mut self, palette: &str) -> Result<(), JsValue> {
+        self.palette = palette
+            .parse()
+            .map_err(|err: String| JsValue::from_str(&err))?;
+        if let Some(gameboy) = self.inner.as_mut() {
+            gameboy.set_dmg_palette(self.palette);
+        }
+        Ok(())
+    }
+
+    // dmg0, dmg, mgb, sgb, sgb2, cgb or agb for the next start, picked from the cartridge and
+    // bios when it's never set
+    pub fn set_model(&mut self, model: &str) -> Result<(), JsValue> {
+        self.model = Some(
+            model
+                .parse()
+                .map_err(|err: String| JsValue::from_str(&err))?,
+        );
+        Ok(())
+    }
+
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        if let Some(gameboy) = self.inner.as_mut() {
+            gameboy.take_audio_samples()
+        } else {
+            vec![]
+        }
+    }
+
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        if let Some(gameboy) = self.inner.as_mut() {
+            gameboy.set_channel_muted(channel, muted);
+        }
+    }
+
+    pub fn set_channel_solo(&mut self, channel: usize, solo: bool) {
+        if let Some(gameboy) = self.inner.as_mut() {
+            gameboy.set_channel_solo(channel, solo);
+        }
+    }
+
+    // the default state for a channel that doesn't exist
+    pub fn channel_state(&self, channel: usize) -> ChannelState {
+        self.inner
+            .as_ref()
+            .and_then(|gameboy| gameboy.channel_state(channel))
+            .map(ChannelState::from)
+            .unwrap_or_default()
+    }
+
+    pub fn set_capture_channels(&mut self, capture: bool) {
+        if let Some(gameboy) = self.inner.as_mut() {
+            gameboy.set_capture_channels(capture);
+        }
+    }
+
+    pub fn channel_samples(&mut self, channel: usize) -> Vec<f32> {
+        if let Some(gameboy) = self.inner.as_mut() {
+            gameboy.take_channel_samples(channel)
+        } else {
+            vec![]
+        }
+    }
+
+    // the illegal opcode that locked the cpu up, reported once
+    pub fn take_lockup(&mut self) -> Option<Lockup> {
+        self.inner
+            .as_mut()
+            .and_then(|gameboy| gameboy.take_lockup())
+            .map(Lockup::from)
+    }
+
+    // memory without side effects, 0xFF before the gameboy is started
+    pub fn peek(&self, address: u16) -> u8 {
+        self.inner
+            .as_ref()
+            .map_or(0xFF, |gameboy| gameboy.peek(address))
+    }
+
+    pub fn poke(&mut self, address: u16, value: u8) {
+        if let Some(gameboy) = self.inner.as_mut() {
+            gameboy.poke(address, value);
+        }
+    }
+
+    pub fn peek_rom(&self, bank: usize, address: u16) -> u8 {
+        self.inner
+            .as_ref()
+            .map_or(0xFF, |gameboy| gameboy.peek_rom(bank, address))
+    }
+
+    pub fn peek_vram(&self, bank: usize, address: u16) -> u8 {
+        self.inner
+            .as_ref()
+            .map_or(0xFF, |gameboy| gameboy.peek_vram(bank, address))
+    }
+
+    pub fn peek_wram(&self, bank: usize, address: u16) -> u8 {
+        self.inner
+            .as_ref()
+            .map_or(0xFF, |gameboy| gameboy.peek_wram(bank, address))
+    }
+
+    pub fn peek_sram(&self, bank: usize, address: u16) -> u8 {
+        self.inner
+            .as_ref()
+            .map_or(0xFF, |gameboy| gameboy.peek_sram(bank, address))
+    }
+
     pub fn is_gbc(&mut self) -> bool {
         let cartridge = GameBoy_::get_cartridge(self.rom.clone());
         cartridge.gbc_flag()
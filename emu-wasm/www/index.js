@@ -8,6 +8,8 @@ class Emulator {
     this.gameboy = null;
     this.running = false;
     this.gbc = false;
+    this.audioCtx = null;
+    this.audioTime = 0;
 
     this.canvas = document.getElementById("game-of-life-canvas");
     this.ctx = this.canvas.getContext("2d");
@@ -21,10 +23,51 @@ class Emulator {
   }
 
   start() {
+    this.init_audio();
     this.gameboy.start();
     this.run();
   }
 
+  init_audio() {
+    const AudioContext = window.AudioContext || window.webkitAudioContext;
+    if (this.audioCtx == null && AudioContext) {
+      this.audioCtx = new AudioContext();
+    }
+    if (this.audioCtx != null) {
+      this.gameboy.set_sample_rate(this.audioCtx.sampleRate);
+    }
+  }
+
+  resume_audio() {
+    if (this.audioCtx != null && this.audioCtx.state === "suspended") {
+      this.audioCtx.resume();
+    }
+  }
+
+  playAudio() {
+    const samples = this.gameboy.audio_samples();
+    if (this.audioCtx == null || samples.length == 0) {
+      return;
+    }
+    const frames = samples.length / 2;
+    const buffer = this.audioCtx.createBuffer(2, frames, this.audioCtx.sampleRate);
+    const left = buffer.getChannelData(0);
+    const right = buffer.getChannelData(1);
+    for (var i = 0; i < frames; i += 1) {
+      left[i] = samples[i * 2];
+      right[i] = samples[i * 2 + 1];
+    }
+    const source = this.audioCtx.createBufferSource();
+    source.buffer = buffer;
+    source.connect(this.audioCtx.destination);
+    const now = this.audioCtx.currentTime;
+    if (this.audioTime < now) {
+      this.audioTime = now + 0.05;
+    }
+    source.start(this.audioTime);
+    this.audioTime += buffer.duration;
+  }
+
   load_cartridge(romBuffer) {
     const rom = new Uint8Array(romBuffer);
     try {
@@ -73,6 +116,7 @@ class Emulator {
 
   renderFrame() {
     const frameBufferPtr = this.gameboy.frame();
+    this.playAudio();
     const frameBuffer = new Uint8Array(memory.buffer, frameBufferPtr,
       this.lcd_width * this.lcd_height * 4);
     const imageData = this.ctx.createImageData(this.lcd_width, this.lcd_height);
@@ -136,6 +180,7 @@ class Emulator {
       return;
     }
 
+    this.resume_audio();
     const keyCode = keyEvent.code;
     const joypad_input = this.mapKeyCodeToInput(keyCode);
 
@@ -0,0 +1,73 @@
+extern crate cpal;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+pub struct Audio {
+    pub sample_rate: u32,
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+    _stream: cpal::Stream,
+}
+
+impl Audio {
+    pub fn init() -> Option<Self> {
+        let host = cpal::default_host();
+        let device = host.default_output_device()?;
+        let config = device.default_output_config().ok()?;
+        let sample_rate = config.sample_rate().0;
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, &buffer),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, &buffer),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, &buffer),
+            format => {
+                eprintln!("audio disabled: unsupported sample format {}", format);
+                return None;
+            }
+        }?;
+        stream.play().ok()?;
+        Some(Audio {
+            sample_rate,
+            buffer,
+            _stream: stream,
+        })
+    }
+    pub fn push(&mut self, samples: &[f32]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.extend(samples);
+        // drop the oldest samples when the emulator runs ahead of the device
+        let max_len = self.sample_rate as usize;
+        while buffer.len() > max_len {
+            buffer.pop_front();
+        }
+    }
+}
+
+// the samples are made as f32 and converted to what the device takes
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    buffer: &Arc<Mutex<VecDeque<f32>>>,
+) -> Option<cpal::Stream> {
+    let channels = config.channels() as usize;
+    let buffer = buffer.clone();
+    device
+        .build_output_stream(
+            &config.config(),
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut buffer = buffer.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let left = buffer.pop_front().unwrap_or(0.0);
+                    let right = buffer.pop_front().unwrap_or(0.0);
+                    for (index, sample) in frame.iter_mut().enumerate() {
+                        let value = if index & 0x01 == 0 { left } else { right };
+                        *sample = T::from_sample(value);
+                    }
+                }
+            },
+            |err| eprintln!("audio stream error: {}", err),
+            None,
+        )
+        .ok()
+}
@@ -1 +1,3 @@
-pub mod display;
\ No newline at end of file
+pub mod display;
+#[cfg(feature = "audio")]
+pub mod audio;
\ No newline at end of file
@@ -1,10 +1,16 @@
 use minifb::KeyRepeat;
+#[cfg(feature = "audio")]
+use rust_gameboy::audio::Audio;
 use rust_gameboy::display::Display;
 use rust_gameboy_core::cartridge::Stable;
-use rust_gameboy_core::gameboy::{GameBoy, HEIGHT, WIDTH};
+use rust_gameboy_core::disasm::disassemble_block;
+#[cfg(feature = "audio")]
+use rust_gameboy_core::gameboy::FRAME_CYCLES;
+use rust_gameboy_core::gameboy::{DmgPalette, GameBoy, Model, HEIGHT, WIDTH};
 use rust_gameboy_core::joypad;
 use rust_gameboy_core::util::read_rom;
-use std::io::Write;
+use rust_gameboy_core::wav::WavWriter;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 use std::{fs::File, path::PathBuf};
 // use std::time::SystemTime;
@@ -22,6 +28,8 @@ struct Args {
 enum Subcommands {
     Info(InfoArgs),
     Run(RunArgs),
+    RecordAudio(RecordAudioArgs),
+    Disasm(DisasmArgs),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -31,11 +39,61 @@ struct RunArgs {
     #[argh(option, short = 'b')]
     /// path to bios file
     bios_path: Option<String>,
+    #[argh(option)]
+    /// write a gameboy-doctor trace of every instruction to the file
+    trace: Option<String>,
+    #[argh(option, short = 'p', default = "DmgPalette::default()")]
+    /// colors of DMG games: green, grayscale, pocket, or 4 comma separated RRGGBB colors, 12
+    /// for separate bg, obj0 and obj1 palettes. P switches between them while running
+    palette: DmgPalette,
+    #[argh(option, short = 'm')]
+    /// hardware to emulate: dmg0, dmg, mgb, sgb, sgb2, cgb or agb, picked from the cartridge
+    /// and bios when left out
+    model: Option<Model>,
     #[argh(positional)]
     /// path to rom file    
     rom_path: String,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "record-audio")]
+/// Record Audio Without Window
+struct RecordAudioArgs {
+    #[argh(option, short = 'b')]
+    /// path to bios file
+    bios_path: Option<String>,
+    #[argh(option, short = 'm')]
+    /// hardware to emulate, picked from the cartridge and bios when left out
+    model: Option<Model>,
+    #[argh(option, short = 'f', default = "600")]
+    /// number of frames to run
+    frames: usize,
+    #[argh(option, short = 'r', default = "44100")]
+    /// sample rate of the wav file
+    sample_rate: u32,
+    #[argh(option, short = 'i')]
+    /// path to input script, one `<frame> <key> <down|up>` per line
+    input_path: Option<String>,
+    #[argh(option, short = 'o')]
+    /// path to wav file, defaults to the rom path with .wav extension
+    output_path: Option<String>,
+    #[argh(positional)]
+    /// path to rom file
+    rom_path: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "disasm")]
+/// Disassemble A Rom Bank
+struct DisasmArgs {
+    #[argh(option, short = 'n', default = "0")]
+    /// rom bank number, bank 0 is mapped at 0x0000 and the others at 0x4000
+    bank: usize,
+    #[argh(positional)]
+    /// path to rom file
+    rom_path: String,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "info")]
 /// Show Info
@@ -45,19 +103,40 @@ struct InfoArgs {
     rom_path: String,
 }
 
-fn start_game(bios_path: impl AsRef<Path>, rom_path: impl AsRef<Path>) {
+fn start_game(
+    bios_path: impl AsRef<Path>,
+    rom_path: impl AsRef<Path>,
+    trace_path: Option<String>,
+    palette: DmgPalette,
+    model: Option<Model>,
+) {
     let ram_path = PathBuf::from(rom_path.as_ref()).with_extension("sav");
     let status_path = PathBuf::from(rom_path.as_ref()).with_extension("status");
 
     let bios = read_rom(bios_path).unwrap_or(vec![]);
     let rom = read_rom(rom_path).unwrap();
     let cartridge = GameBoy::get_cartridge(rom.clone());
-    let mut gameboy = GameBoy::new(bios, cartridge);
+    let mut gameboy = match model {
+        Some(model) => GameBoy::with_model(model, bios, cartridge),
+        None => GameBoy::new(bios, cartridge),
+    };
+    gameboy.set_dmg_palette(palette);
+    let palettes = [
+        palette,
+        DmgPalette::GREEN,
+        DmgPalette::GRAYSCALE,
+        DmgPalette::POCKET,
+    ];
+    let mut palette_index = 0;
     let ram_path = ram_path.to_str().unwrap();
     let ram_result = read_rom(ram_path);
     if let Ok(ram) = ram_result {
         gameboy.load_sav(ram);
     }
+    if let Some(trace_path) = trace_path {
+        let file = File::create(trace_path).unwrap();
+        gameboy.set_trace(Some(Box::new(BufWriter::new(file))));
+    }
     let status_path = status_path.to_str().unwrap();
     /*
     let status_result = read_rom(status_path);
@@ -68,6 +147,14 @@ fn start_game(bios_path: impl AsRef<Path>, rom_path: impl AsRef<Path>) {
     }
     */
     let mut display = Display::init(WIDTH, HEIGHT);
+    #[cfg(feature = "audio")]
+    let mut audio = Audio::init();
+    #[cfg(feature = "audio")]
+    if let Some(audio) = audio.as_ref() {
+        gameboy.set_sample_rate(audio.sample_rate);
+    }
+    #[cfg(feature = "audio")]
+    let mut audio_cycles = 0;
     /*
     let mut start_time = SystemTime::now()
     .duration_since(SystemTime::UNIX_EPOCH)
@@ -110,6 +197,23 @@ fn start_game(bios_path: impl AsRef<Path>, rom_path: impl AsRef<Path>) {
         */
 
         let is_refresh = gameboy.trick();
+        if let Some(lockup) = gameboy.take_lockup() {
+            println!(
+                "cpu locked up by illegal opcode {:02X} at {:04X}",
+                lockup.opcode, lockup.address
+            );
+        }
+        // a frame of sound at a time, there are no refreshes while the LCD is off
+        #[cfg(feature = "audio")]
+        {
+            audio_cycles += 1;
+            if audio_cycles == FRAME_CYCLES {
+                audio_cycles = 0;
+                if let Some(audio) = audio.as_mut() {
+                    audio.push(&gameboy.take_audio_samples());
+                }
+            }
+        }
         if is_refresh {
             let frame_buffer = gameboy.get_frame_buffer();
             buffer.clone_from_slice(frame_buffer);
@@ -126,6 +230,10 @@ fn start_game(bios_path: impl AsRef<Path>, rom_path: impl AsRef<Path>) {
                     gameboy.input(vk.clone(), false);
                 }
             }
+            if display.window.is_key_pressed(minifb::Key::P, KeyRepeat::No) {
+                palette_index = (palette_index + 1) % palettes.len();
+                gameboy.set_dmg_palette(palettes[palette_index]);
+            }
             if display.window.is_key_pressed(minifb::Key::O, KeyRepeat::No) {
                 let ram = gameboy.save_sav();
                 File::create(ram_path)
@@ -151,6 +259,70 @@ fn start_game(bios_path: impl AsRef<Path>, rom_path: impl AsRef<Path>) {
     }
 }
 
+fn read_input_script(path: impl AsRef<Path>) -> Vec<(usize, joypad::JoyPadKey, bool)> {
+    let script = std::fs::read_to_string(path).unwrap();
+    let mut events = vec![];
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let event = match parts.as_slice() {
+            [frame, key, state] => frame.parse::<usize>().ok().and_then(|frame| {
+                let key = key.parse::<joypad::JoyPadKey>().ok()?;
+                match *state {
+                    "down" => Some((frame, key, true)),
+                    "up" => Some((frame, key, false)),
+                    _ => None,
+                }
+            }),
+            _ => None,
+        };
+        match event {
+            Some(event) => events.push(event),
+            None => panic!("invalid input script at line {}: {}", i + 1, line),
+        }
+    }
+    events.sort_by_key(|(frame, _, _)| *frame);
+    events
+}
+
+fn record_audio(args: RecordAudioArgs) {
+    let output_path = args
+        .output_path
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(&args.rom_path).with_extension("wav"));
+    let events = args.input_path.map(read_input_script).unwrap_or_default();
+
+    let bios = args
+        .bios_path
+        .map(|path| read_rom(path).unwrap())
+        .unwrap_or_default();
+    let rom = read_rom(&args.rom_path).unwrap();
+    let cartridge = GameBoy::get_cartridge(rom);
+    let mut gameboy = match args.model {
+        Some(model) => GameBoy::with_model(model, bios, cartridge),
+        None => GameBoy::new(bios, cartridge),
+    };
+    gameboy.set_sample_rate(args.sample_rate);
+
+    let file = File::create(&output_path).unwrap();
+    let mut writer = WavWriter::new(BufWriter::new(file), args.sample_rate).unwrap();
+    let mut events = events.into_iter().peekable();
+    for frame in 0..args.frames {
+        while let Some((_, key, pressed)) = events.next_if(|(f, _, _)| *f <= frame) {
+            gameboy.input(key, pressed);
+        }
+        gameboy.run_frame();
+        writer
+            .write_samples(&gameboy.take_audio_samples_i16())
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    println!("wrote {}", output_path.display());
+}
+
 fn main() {
     let args: Args = argh::from_env();
     let command = args.nested.unwrap_or_else(|| {
@@ -160,7 +332,50 @@ fn main() {
 
     match command {
         Subcommands::Run(subargs) => {
-            start_game(subargs.bios_path.unwrap_or("".to_owned()), subargs.rom_path);
+            start_game(
+                subargs.bios_path.unwrap_or("".to_owned()),
+                subargs.rom_path,
+                subargs.trace,
+                subargs.palette,
+                subargs.model,
+            );
+        }
+        Subcommands::RecordAudio(subargs) => {
+            record_audio(subargs);
+        }
+        Subcommands::Disasm(subargs) => {
+            let rom = read_rom(subargs.rom_path).unwrap();
+            let start = subargs.bank * 0x4000;
+            if start >= rom.len() {
+                println!("bank {} is out of range", subargs.bank);
+                std::process::exit(1);
+            }
+            let bytes = &rom[start..(start + 0x4000).min(rom.len())];
+            let address = if subargs.bank == 0 { 0x0000 } else { 0x4000 };
+            for instruction in disassemble_block(address, bytes) {
+                let bytes = instruction
+                    .bytes
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                // the bank ends before the operands, show what there is as data
+                let mnemonic = if instruction.truncated {
+                    let data = instruction
+                        .bytes
+                        .iter()
+                        .map(|byte| format!("${:02X}", byte))
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    format!("DB {}", data)
+                } else {
+                    instruction.mnemonic
+                };
+                println!(
+                    "{:02X}:{:04X}  {:<8}  {}",
+                    subargs.bank, instruction.address, bytes, mnemonic
+                );
+            }
         }
         Subcommands::Info(subargs) => {
             let rom = read_rom(subargs.rom_path).unwrap();
@@ -0,0 +1,1073 @@
+use crate::gameboy_mode::GameBoyMode;
+use crate::memory::Memory;
+use crate::util::check_bit;
+
+pub const CLOCK_FREQUENCY: u32 = 4194304;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+pub const CHANNELS: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelState {
+    pub enable: bool,
+    pub dac_enable: bool,
+    // period value of NRx3/NRx4, NR43 for the noise channel
+    pub frequency: u16,
+    // tone frequency, LFSR clock for the noise channel
+    pub frequency_hz: f32,
+    // envelope volume, volume code for the wave channel
+    pub volume: u8,
+    pub duty: u8,
+    pub envelope_increase: bool,
+    pub envelope_period: u8,
+    // digital output 0x0~0xF
+    pub output: u8,
+    pub muted: bool,
+    pub solo: bool,
+}
+
+// the five registers of a channel, NRx0~NRx4
+#[derive(Clone, Copy)]
+enum Register {
+    Nrx0,
+    Nrx1,
+    Nrx2,
+    Nrx3,
+    Nrx4,
+}
+impl Register {
+    // index lies in the five registers starting at base
+    fn new(index: u16, base: u16) -> Self {
+        match index - base {
+            0 => Register::Nrx0,
+            1 => Register::Nrx1,
+            2 => Register::Nrx2,
+            3 => Register::Nrx3,
+            _ => Register::Nrx4,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct LengthCounter {
+    enable: bool,
+    counter: u16,
+    max: u16,
+}
+impl LengthCounter {
+    fn new(max: u16) -> Self {
+        Self {
+            enable: false,
+            counter: 0,
+            max,
+        }
+    }
+    fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+    // return true when the channel has to be disabled
+    fn trick(&mut self) -> bool {
+        if self.enable && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+    // NRx4 write. When the next frame sequencer step doesn't clock the length counter,
+    // enabling the length counter clocks it once more.
+    fn write_nrx4(&mut self, value: u8, frame_sequencer_next_length: bool) -> bool {
+        let enable = check_bit(value, 6);
+        let trigger = check_bit(value, 7);
+        let mut disable = false;
+        if !frame_sequencer_next_length && !self.enable && enable && self.counter > 0 {
+            self.counter -= 1;
+            disable = self.counter == 0 && !trigger;
+        }
+        self.enable = enable;
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enable && !frame_sequencer_next_length {
+                self.counter -= 1;
+            }
+        }
+        disable
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    volume: u8,
+}
+impl Envelope {
+    fn new() -> Self {
+        Self {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            timer: 0,
+            volume: 0,
+        }
+    }
+    fn get_nrx2(&self) -> u8 {
+        self.initial_volume << 4 | (self.increase as u8) << 3 | self.period
+    }
+    fn set_nrx2(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = check_bit(value, 3);
+        self.period = value & 0x07;
+    }
+    fn dac_enable(&self) -> bool {
+        self.get_nrx2() & 0xF8 != 0
+    }
+    fn trigger(&mut self) {
+        self.timer = self.period;
+        self.volume = self.initial_volume;
+    }
+    fn trick(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 0x0F {
+                self.volume += 1;
+            }
+            if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enable: bool,
+    shadow_frequency: u16,
+    negate_used: bool,
+}
+impl Sweep {
+    fn new() -> Self {
+        Self {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            enable: false,
+            shadow_frequency: 0,
+            negate_used: false,
+        }
+    }
+    fn get_nr10(&self) -> u8 {
+        self.period << 4 | (self.negate as u8) << 3 | self.shift
+    }
+    fn store_nr10(&mut self, value: u8) {
+        self.period = (value >> 4) & 0x07;
+        self.negate = check_bit(value, 3);
+        self.shift = value & 0x07;
+    }
+    // return true when the channel has to be disabled
+    fn set_nr10(&mut self, value: u8) -> bool {
+        self.store_nr10(value);
+        // leaving negate mode after a negate calculation disables the channel
+        let disable = !self.negate && self.negate_used;
+        self.negate_used = false;
+        disable
+    }
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.shift;
+        if self.negate {
+            self.negate_used = true;
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+    // return true when the channel has to be disabled
+    fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow_frequency = frequency;
+        self.reload_timer();
+        self.enable = self.period != 0 || self.shift != 0;
+        self.negate_used = false;
+        self.shift != 0 && self.calculate() > 0x07FF
+    }
+    // return the new frequency, or Err(()) when the channel has to be disabled
+    fn trick(&mut self) -> Result<Option<u16>, ()> {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer != 0 {
+            return Ok(None);
+        }
+        self.reload_timer();
+        if !self.enable || self.period == 0 {
+            return Ok(None);
+        }
+        let frequency = self.calculate();
+        if frequency > 0x07FF {
+            return Err(());
+        }
+        if self.shift == 0 {
+            return Ok(None);
+        }
+        self.shadow_frequency = frequency;
+        if self.calculate() > 0x07FF {
+            return Err(());
+        }
+        Ok(Some(frequency))
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct SquareChannel {
+    enable: bool,
+    sweep: Option<Sweep>,
+    duty: u8,
+    duty_position: u8,
+    length: LengthCounter,
+    envelope: Envelope,
+    frequency: u16,
+    timer: u32,
+}
+impl SquareChannel {
+    fn new(with_sweep: bool) -> Self {
+        Self {
+            enable: false,
+            sweep: if with_sweep { Some(Sweep::new()) } else { None },
+            duty: 0,
+            duty_position: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            frequency: 0,
+            timer: 0,
+        }
+    }
+    fn trick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency as u32) * 4;
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+    }
+    fn trick_length(&mut self) {
+        if self.length.trick() {
+            self.enable = false;
+        }
+    }
+    fn trick_sweep(&mut self) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            match sweep.trick() {
+                Ok(Some(frequency)) => self.frequency = frequency,
+                Ok(None) => {}
+                Err(()) => self.enable = false,
+            }
+        }
+    }
+    fn trigger(&mut self) {
+        self.enable = self.envelope.dac_enable();
+        self.timer = (2048 - self.frequency as u32) * 4;
+        self.envelope.trigger();
+        if let Some(sweep) = self.sweep.as_mut() {
+            if sweep.trigger(self.frequency) {
+                self.enable = false;
+            }
+        }
+    }
+    fn output(&self) -> u8 {
+        if !self.enable {
+            return 0;
+        }
+        DUTY_TABLE[self.duty as usize][self.duty_position as usize] * self.envelope.volume
+    }
+    fn dac_enable(&self) -> bool {
+        self.envelope.dac_enable()
+    }
+    fn state(&self) -> ChannelState {
+        ChannelState {
+            enable: self.enable,
+            dac_enable: self.dac_enable(),
+            frequency: self.frequency,
+            frequency_hz: 131072.0 / (2048 - self.frequency as u32) as f32,
+            volume: self.envelope.volume,
+            duty: self.duty,
+            envelope_increase: self.envelope.increase,
+            envelope_period: self.envelope.period,
+            output: self.output(),
+            ..Default::default()
+        }
+    }
+    fn get(&self, register: Register) -> u8 {
+        match register {
+            Register::Nrx0 => match self.sweep.as_ref() {
+                Some(sweep) => sweep.get_nr10() | 0x80,
+                None => 0xFF,
+            },
+            Register::Nrx1 => self.duty << 6 | 0x3F,
+            Register::Nrx2 => self.envelope.get_nrx2(),
+            Register::Nrx3 => 0xFF,
+            Register::Nrx4 => (self.length.enable as u8) << 6 | 0xBF,
+        }
+    }
+    fn set(&mut self, register: Register, value: u8, frame_sequencer_next_length: bool) {
+        match register {
+            Register::Nrx0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    if sweep.set_nr10(value) {
+                        self.enable = false;
+                    }
+                }
+            }
+            Register::Nrx1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            Register::Nrx2 => {
+                self.envelope.set_nrx2(value);
+                if !self.dac_enable() {
+                    self.enable = false;
+                }
+            }
+            Register::Nrx3 => {
+                self.frequency = self.frequency & 0x0700 | value as u16;
+            }
+            Register::Nrx4 => {
+                self.frequency = self.frequency & 0x00FF | ((value & 0x07) as u16) << 8;
+                if self.length.write_nrx4(value, frame_sequencer_next_length) {
+                    self.enable = false;
+                }
+                if check_bit(value, 7) {
+                    self.trigger();
+                }
+            }
+        }
+    }
+    // the register as it reads afterwards, without what writing it does to the channel
+    fn store(&mut self, register: Register, value: u8) {
+        match register {
+            Register::Nrx0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    sweep.store_nr10(value);
+                }
+            }
+            Register::Nrx1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            Register::Nrx2 => self.envelope.set_nrx2(value),
+            Register::Nrx3 => self.frequency = self.frequency & 0x0700 | value as u16,
+            Register::Nrx4 => {
+                self.frequency = self.frequency & 0x00FF | ((value & 0x07) as u16) << 8;
+                self.length.enable = check_bit(value, 6);
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct WaveChannel {
+    enable: bool,
+    dac_enable: bool,
+    length: LengthCounter,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    sample_buffer: u8,
+    // cycles since the last wave RAM fetch
+    fetch_cycles: u8,
+    ram: [u8; 16],
+}
+impl WaveChannel {
+    fn new() -> Self {
+        Self {
+            enable: false,
+            dac_enable: false,
+            length: LengthCounter::new(256),
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            fetch_cycles: u8::MAX,
+            ram: [0; 16],
+        }
+    }
+    fn trick(&mut self) {
+        self.fetch_cycles = self.fetch_cycles.saturating_add(1);
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency as u32) * 2;
+            self.position = (self.position + 1) % 32;
+            self.sample_buffer = self.ram[self.position as usize / 2];
+            self.fetch_cycles = 0;
+        }
+    }
+    // While playing, the CPU sees the byte the channel is reading. The DMG only allows
+    // the access at the moment the channel fetches it.
+    fn ram_index(&self, index: usize, mode: GameBoyMode) -> Option<usize> {
+        if !self.enable {
+            return Some(index);
+        }
+        if mode == GameBoyMode::GBC || self.fetch_cycles < 4 {
+            Some(self.position as usize / 2)
+        } else {
+            None
+        }
+    }
+    fn get_ram(&self, index: usize, mode: GameBoyMode) -> u8 {
+        match self.ram_index(index, mode) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+    fn set_ram(&mut self, index: usize, value: u8, mode: GameBoyMode) {
+        if let Some(index) = self.ram_index(index, mode) {
+            self.ram[index] = value;
+        }
+    }
+    // Retriggering the DMG right before a fetch corrupts the first bytes of wave RAM
+    // with the bytes about to be read.
+    fn corrupt_ram(&mut self) {
+        if !self.enable || self.timer != 1 {
+            return;
+        }
+        let index = ((self.position as usize + 1) % 32) / 2;
+        if index < 4 {
+            self.ram[0] = self.ram[index];
+        } else {
+            let block = index & !0x03;
+            for offset in 0..4 {
+                self.ram[offset] = self.ram[block + offset];
+            }
+        }
+    }
+    fn trick_length(&mut self) {
+        if self.length.trick() {
+            self.enable = false;
+        }
+    }
+    fn trigger(&mut self) {
+        self.enable = self.dac_enable;
+        // the first sample is fetched after a short delay
+        self.timer = (2048 - self.frequency as u32) * 2 + 6;
+        self.position = 0;
+    }
+    fn output(&self) -> u8 {
+        if !self.enable {
+            return 0;
+        }
+        let sample = if self.position & 0x01 == 0 {
+            self.sample_buffer >> 4
+        } else {
+            self.sample_buffer & 0x0F
+        };
+        match self.volume_code {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            _ => sample >> 2,
+        }
+    }
+    fn state(&self) -> ChannelState {
+        ChannelState {
+            enable: self.enable,
+            dac_enable: self.dac_enable,
+            frequency: self.frequency,
+            frequency_hz: 65536.0 / (2048 - self.frequency as u32) as f32,
+            volume: self.volume_code,
+            output: self.output(),
+            ..Default::default()
+        }
+    }
+    fn get(&self, register: Register) -> u8 {
+        match register {
+            Register::Nrx0 => (self.dac_enable as u8) << 7 | 0x7F,
+            Register::Nrx1 => 0xFF,
+            Register::Nrx2 => self.volume_code << 5 | 0x9F,
+            Register::Nrx3 => 0xFF,
+            Register::Nrx4 => (self.length.enable as u8) << 6 | 0xBF,
+        }
+    }
+    fn set(&mut self, register: Register, value: u8, frame_sequencer_next_length: bool) {
+        match register {
+            Register::Nrx0 => {
+                self.dac_enable = check_bit(value, 7);
+                if !self.dac_enable {
+                    self.enable = false;
+                }
+            }
+            Register::Nrx1 => {
+                self.length.load(value);
+            }
+            Register::Nrx2 => {
+                self.volume_code = (value >> 5) & 0x03;
+            }
+            Register::Nrx3 => {
+                self.frequency = self.frequency & 0x0700 | value as u16;
+            }
+            Register::Nrx4 => {
+                self.frequency = self.frequency & 0x00FF | ((value & 0x07) as u16) << 8;
+                if self.length.write_nrx4(value, frame_sequencer_next_length) {
+                    self.enable = false;
+                }
+                if check_bit(value, 7) {
+                    self.trigger();
+                }
+            }
+        }
+    }
+    fn store(&mut self, register: Register, value: u8) {
+        match register {
+            Register::Nrx0 => self.dac_enable = check_bit(value, 7),
+            Register::Nrx1 => self.length.load(value),
+            Register::Nrx2 => self.volume_code = (value >> 5) & 0x03,
+            Register::Nrx3 => self.frequency = self.frequency & 0x0700 | value as u16,
+            Register::Nrx4 => {
+                self.frequency = self.frequency & 0x00FF | ((value & 0x07) as u16) << 8;
+                self.length.enable = check_bit(value, 6);
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct NoiseChannel {
+    enable: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    clock_shift: u8,
+    width_mode: bool,
+    divisor_code: u8,
+    timer: u32,
+    lfsr: u16,
+}
+impl NoiseChannel {
+    fn new() -> Self {
+        Self {
+            enable: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            clock_shift: 0,
+            width_mode: false,
+            divisor_code: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+        }
+    }
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+    fn trick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            // clock shift 14 and 15 stop the LFSR
+            if self.clock_shift >= 14 {
+                return;
+            }
+            let xor = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if self.width_mode {
+                self.lfsr = self.lfsr & !(1 << 6) | (xor << 6);
+            }
+        }
+    }
+    fn trick_length(&mut self) {
+        if self.length.trick() {
+            self.enable = false;
+        }
+    }
+    fn trigger(&mut self) {
+        self.enable = self.envelope.dac_enable();
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+    fn output(&self) -> u8 {
+        if !self.enable {
+            return 0;
+        }
+        (!self.lfsr & 0x01) as u8 * self.envelope.volume
+    }
+    fn dac_enable(&self) -> bool {
+        self.envelope.dac_enable()
+    }
+    fn state(&self) -> ChannelState {
+        ChannelState {
+            enable: self.enable,
+            dac_enable: self.dac_enable(),
+            frequency: self.get(Register::Nrx3) as u16,
+            frequency_hz: CLOCK_FREQUENCY as f32 / self.period() as f32,
+            volume: self.envelope.volume,
+            envelope_increase: self.envelope.increase,
+            envelope_period: self.envelope.period,
+            output: self.output(),
+            ..Default::default()
+        }
+    }
+    fn get(&self, register: Register) -> u8 {
+        match register {
+            Register::Nrx0 => 0xFF,
+            Register::Nrx1 => 0xFF,
+            Register::Nrx2 => self.envelope.get_nrx2(),
+            Register::Nrx3 => {
+                self.clock_shift << 4 | (self.width_mode as u8) << 3 | self.divisor_code
+            }
+            Register::Nrx4 => (self.length.enable as u8) << 6 | 0xBF,
+        }
+    }
+    fn set(&mut self, register: Register, value: u8, frame_sequencer_next_length: bool) {
+        match register {
+            Register::Nrx0 => {}
+            Register::Nrx1 => {
+                self.length.load(value & 0x3F);
+            }
+            Register::Nrx2 => {
+                self.envelope.set_nrx2(value);
+                if !self.dac_enable() {
+                    self.enable = false;
+                }
+            }
+            Register::Nrx3 => {
+                self.clock_shift = value >> 4;
+                self.width_mode = check_bit(value, 3);
+                self.divisor_code = value & 0x07;
+            }
+            Register::Nrx4 => {
+                if self.length.write_nrx4(value, frame_sequencer_next_length) {
+                    self.enable = false;
+                }
+                if check_bit(value, 7) {
+                    self.trigger();
+                }
+            }
+        }
+    }
+    fn store(&mut self, register: Register, value: u8) {
+        match register {
+            Register::Nrx0 => {}
+            Register::Nrx1 => self.length.load(value & 0x3F),
+            Register::Nrx2 => self.envelope.set_nrx2(value),
+            Register::Nrx3 => {
+                self.clock_shift = value >> 4;
+                self.width_mode = check_bit(value, 3);
+                self.divisor_code = value & 0x07;
+            }
+            Register::Nrx4 => self.length.enable = check_bit(value, 6),
+        }
+    }
+}
+
+// Averages the output over every host sample period and removes the DC offset
+// like the output capacitor of the real hardware does.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+struct SampleBuffer {
+    sample_rate: u32,
+    charge: f32,
+    cycles: u32,
+    count: u32,
+    sum: (f32, f32),
+    capacitor: (f32, f32),
+    #[serde(skip)]
+    samples: Vec<f32>,
+}
+impl SampleBuffer {
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.charge = if sample_rate == 0 {
+            0.0
+        } else {
+            0.999958_f32.powf(CLOCK_FREQUENCY as f32 / sample_rate as f32)
+        };
+        self.cycles = 0;
+        self.count = 0;
+        self.sum = (0.0, 0.0);
+        self.samples.clear();
+    }
+    // true when a host sample is produced
+    fn push(&mut self, (left, right): (f32, f32)) -> bool {
+        if self.sample_rate == 0 {
+            return false;
+        }
+        self.sum.0 += left;
+        self.sum.1 += right;
+        self.count += 1;
+        self.cycles += self.sample_rate;
+        if self.cycles < CLOCK_FREQUENCY {
+            return false;
+        }
+        self.cycles -= CLOCK_FREQUENCY;
+        let left = self.sum.0 / self.count as f32;
+        let right = self.sum.1 / self.count as f32;
+        self.sum = (0.0, 0.0);
+        self.count = 0;
+        let left_out = left - self.capacitor.0;
+        let right_out = right - self.capacitor.1;
+        self.capacitor.0 = left - left_out * self.charge;
+        self.capacitor.1 = right - right_out * self.charge;
+        self.samples.push(left_out);
+        self.samples.push(right_out);
+        true
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Apu {
+    mode: GameBoyMode,
+    enable: bool,
+    nr50: u8,
+    nr51: u8,
+    frame_sequencer: u8,
+    channel1: SquareChannel,
+    channel2: SquareChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    sample_buffer: SampleBuffer,
+    // debugging state of the host, not part of the hardware
+    #[serde(skip)]
+    muted: [bool; CHANNELS],
+    #[serde(skip)]
+    solo: [bool; CHANNELS],
+    #[serde(skip)]
+    capture_channels: bool,
+    #[serde(skip)]
+    channel_samples: [Vec<f32>; CHANNELS],
+}
+
+impl Apu {
+    pub fn new(mode: GameBoyMode) -> Self {
+        Self {
+            mode,
+            enable: false,
+            nr50: 0,
+            nr51: 0,
+            frame_sequencer: 0,
+            channel1: SquareChannel::new(true),
+            channel2: SquareChannel::new(false),
+            channel3: WaveChannel::new(),
+            channel4: NoiseChannel::new(),
+            sample_buffer: SampleBuffer::default(),
+            muted: [false; CHANNELS],
+            solo: [false; CHANNELS],
+            capture_channels: false,
+            channel_samples: Default::default(),
+        }
+    }
+    pub fn trick(&mut self) {
+        let output = self.output();
+        if self.sample_buffer.push(output) && self.capture_channels {
+            let outputs = self.channel_outputs();
+            for (samples, digital) in self.channel_samples.iter_mut().zip(outputs) {
+                samples.push(digital as f32 / 15.0);
+            }
+        }
+        if !self.enable {
+            return;
+        }
+        self.channel1.trick();
+        self.channel2.trick();
+        self.channel3.trick();
+        self.channel4.trick();
+    }
+    // clocked by the falling edge of DIV bit 4, bit 5 in double speed
+    pub fn trick_frame_sequencer(&mut self) {
+        if !self.enable {
+            return;
+        }
+        match self.frame_sequencer {
+            0 | 4 => {
+                self.trick_length();
+            }
+            2 | 6 => {
+                self.trick_length();
+                self.channel1.trick_sweep();
+            }
+            7 => {
+                self.channel1.envelope.trick();
+                self.channel2.envelope.trick();
+                self.channel4.envelope.trick();
+            }
+            _ => {}
+        }
+        self.frame_sequencer = (self.frame_sequencer + 1) % 8;
+    }
+    fn trick_length(&mut self) {
+        self.channel1.trick_length();
+        self.channel2.trick_length();
+        self.channel3.trick_length();
+        self.channel4.trick_length();
+    }
+    // the step that comes next clocks the length counters
+    fn frame_sequencer_next_length(&self) -> bool {
+        self.frame_sequencer & 0x01 == 0
+    }
+    // The DMG keeps the length counters while powered off, the CGB clears them.
+    fn power_off(&mut self) {
+        let lengths = [
+            self.channel1.length.counter,
+            self.channel2.length.counter,
+            self.channel3.length.counter,
+            self.channel4.length.counter,
+        ];
+        for index in 0xFF10..=0xFF25 {
+            self.set(index, 0x00);
+        }
+        self.enable = false;
+        let lengths = if self.mode == GameBoyMode::GBC {
+            [0; CHANNELS]
+        } else {
+            lengths
+        };
+        self.channel1.length.counter = lengths[0];
+        self.channel2.length.counter = lengths[1];
+        self.channel3.length.counter = lengths[2];
+        self.channel4.length.counter = lengths[3];
+    }
+    fn power_on(&mut self) {
+        self.enable = true;
+        self.frame_sequencer = 0;
+        self.channel1.duty_position = 0;
+        self.channel2.duty_position = 0;
+        self.channel3.sample_buffer = 0;
+    }
+    // 0 stops producing samples
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_buffer.set_sample_rate(sample_rate);
+    }
+    // a register as it would read afterwards, without triggering, clocking or turning off a
+    // channel. NR52 is the power switch and stays as it is.
+    pub fn store(&mut self, index: u16, value: u8) {
+        match index {
+            0xFF10..=0xFF14 => self.channel1.store(Register::new(index, 0xFF10), value),
+            0xFF15..=0xFF19 => self.channel2.store(Register::new(index, 0xFF15), value),
+            0xFF1A..=0xFF1E => self.channel3.store(Register::new(index, 0xFF1A), value),
+            0xFF1F..=0xFF23 => self.channel4.store(Register::new(index, 0xFF1F), value),
+            0xFF24 => self.nr50 = value,
+            0xFF25 => self.nr51 = value,
+            0xFF30..=0xFF3F => self.channel3.ram[(index - 0xFF30) as usize] = value,
+            _ => {}
+        }
+    }
+    // interleaved stereo samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.sample_buffer.samples)
+    }
+    // channels past the 4th are ignored
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        if let Some(channel_muted) = self.muted.get_mut(channel) {
+            *channel_muted = muted;
+        }
+    }
+    // while any channel is soloed only the soloed channels are heard
+    pub fn set_channel_solo(&mut self, channel: usize, solo: bool) {
+        if let Some(channel_solo) = self.solo.get_mut(channel) {
+            *channel_solo = solo;
+        }
+    }
+    pub fn is_channel_audible(&self, channel: usize) -> bool {
+        if channel >= CHANNELS {
+            return false;
+        }
+        if self.solo.iter().any(|solo| *solo) {
+            self.solo[channel] && !self.muted[channel]
+        } else {
+            !self.muted[channel]
+        }
+    }
+    // None past the 4th channel
+    pub fn channel_state(&self, channel: usize) -> Option<ChannelState> {
+        let state = match channel {
+            0 => self.channel1.state(),
+            1 => self.channel2.state(),
+            2 => self.channel3.state(),
+            3 => self.channel4.state(),
+            _ => return None,
+        };
+        Some(ChannelState {
+            muted: self.muted[channel],
+            solo: self.solo[channel],
+            ..state
+        })
+    }
+    // PCM12 and PCM34 on the CGB
+    fn pcm(&self, index: u16) -> u8 {
+        match index {
+            0xFF76 => self.channel2.output() << 4 | self.channel1.output(),
+            _ => self.channel4.output() << 4 | self.channel3.output(),
+        }
+    }
+    fn channel_outputs(&self) -> [u8; CHANNELS] {
+        [
+            self.channel1.output(),
+            self.channel2.output(),
+            self.channel3.output(),
+            self.channel4.output(),
+        ]
+    }
+    // records the digital output of every channel at the sample rate, for oscilloscopes
+    pub fn set_capture_channels(&mut self, capture: bool) {
+        self.capture_channels = capture;
+        if !capture {
+            self.channel_samples = Default::default();
+        }
+    }
+    // samples of one channel in the range 0.0~1.0 produced since the last call, none past the
+    // 4th channel
+    pub fn take_channel_samples(&mut self, channel: usize) -> Vec<f32> {
+        self.channel_samples
+            .get_mut(channel)
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+    // digital channel outputs (0x0~0xF) converted by the DACs and mixed by NR50/NR51,
+    // each side in the range -1.0~1.0
+    pub fn output(&self) -> (f32, f32) {
+        if !self.enable {
+            return (0.0, 0.0);
+        }
+        let channels = [
+            (self.channel1.output(), self.channel1.dac_enable()),
+            (self.channel2.output(), self.channel2.dac_enable()),
+            (self.channel3.output(), self.channel3.dac_enable),
+            (self.channel4.output(), self.channel4.dac_enable()),
+        ];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (index, (digital, dac_enable)) in channels.iter().enumerate() {
+            if !dac_enable || !self.is_channel_audible(index) {
+                continue;
+            }
+            let analog = (*digital as f32) / 7.5 - 1.0;
+            if check_bit(self.nr51, index as u8 + 4) {
+                left += analog;
+            }
+            if check_bit(self.nr51, index as u8) {
+                right += analog;
+            }
+        }
+        let left_volume = ((self.nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0x07) as f32 + 1.0;
+        (
+            left / 4.0 * left_volume / 8.0,
+            right / 4.0 * right_volume / 8.0,
+        )
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new(GameBoyMode::GB)
+    }
+}
+
+impl Memory for Apu {
+    fn get(&self, index: u16) -> u8 {
+        match index {
+            0xFF10..=0xFF14 => self.channel1.get(Register::new(index, 0xFF10)),
+            0xFF15..=0xFF19 => self.channel2.get(Register::new(index, 0xFF15)),
+            0xFF1A..=0xFF1E => self.channel3.get(Register::new(index, 0xFF1A)),
+            0xFF1F..=0xFF23 => self.channel4.get(Register::new(index, 0xFF1F)),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                (self.enable as u8) << 7
+                    | 0x70
+                    | (self.channel4.enable as u8) << 3
+                    | (self.channel3.enable as u8) << 2
+                    | (self.channel2.enable as u8) << 1
+                    | (self.channel1.enable as u8)
+            }
+            0xFF27..=0xFF2F => 0xFF,
+            0xFF30..=0xFF3F => self.channel3.get_ram((index - 0xFF30) as usize, self.mode),
+            0xFF76 | 0xFF77 if self.mode == GameBoyMode::GBC => self.pcm(index),
+            _ => 0xFF,
+        }
+    }
+    fn set(&mut self, index: u16, value: u8) {
+        if index == 0xFF26 {
+            let enable = check_bit(value, 7);
+            if self.enable && !enable {
+                self.power_off();
+            } else if !self.enable && enable {
+                self.power_on();
+            }
+            return;
+        }
+        if let 0xFF30..=0xFF3F = index {
+            self.channel3
+                .set_ram((index - 0xFF30) as usize, value, self.mode);
+            return;
+        }
+        // registers are read-only while powered off, except the length counters on the DMG
+        if !self.enable {
+            if self.mode == GameBoyMode::GB {
+                match index {
+                    0xFF11 => self.channel1.length.load(value & 0x3F),
+                    0xFF16 => self.channel2.length.load(value & 0x3F),
+                    0xFF1B => self.channel3.length.load(value),
+                    0xFF20 => self.channel4.length.load(value & 0x3F),
+                    _ => {}
+                }
+            }
+            return;
+        }
+        let frame_sequencer_next_length = self.frame_sequencer_next_length();
+        match index {
+            0xFF10..=0xFF14 => self.channel1.set(
+                Register::new(index, 0xFF10),
+                value,
+                frame_sequencer_next_length,
+            ),
+            0xFF15..=0xFF19 => self.channel2.set(
+                Register::new(index, 0xFF15),
+                value,
+                frame_sequencer_next_length,
+            ),
+            0xFF1A..=0xFF1E => {
+                if index == 0xFF1E && check_bit(value, 7) && self.mode == GameBoyMode::GB {
+                    self.channel3.corrupt_ram();
+                }
+                self.channel3.set(
+                    Register::new(index, 0xFF1A),
+                    value,
+                    frame_sequencer_next_length,
+                )
+            }
+            0xFF1F..=0xFF23 => self.channel4.set(
+                Register::new(index, 0xFF1F),
+                value,
+                frame_sequencer_next_length,
+            ),
+            0xFF24 => self.nr50 = value,
+            0xFF25 => self.nr51 = value,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::debugger::Access;
+use crate::memory::Memory;
+use std::cell::RefCell;
+
+// The memory map as the cpu sees it. tick is called once for every machine cycle of the
+// cpu, right before the access when the cycle has one.
+pub trait Bus: Memory {
+    fn tick(&mut self) {}
+    // the read of an opcode
+    fn fetch(&self, index: u16) -> u8 {
+        self.get(index)
+    }
+    // IE and IF polling and the trace, which don't go over the bus
+    fn peek(&self, index: u16) -> u8 {
+        self.get(index)
+    }
+    fn poke(&mut self, index: u16, value: u8) {
+        self.set(index, value);
+    }
+    fn double_speed(&self) -> bool {
+        false
+    }
+    fn set_double_speed(&mut self, _double_speed: bool) {}
+    // STOP switches the speed when KEY1 has it armed, false when it doesn't
+    fn switch_speed(&mut self) -> bool {
+        false
+    }
+    // STOP resets DIV
+    fn reset_div(&mut self) {}
+    // a machine cycle of a speed switch, the timer stays put
+    fn stall(&mut self) {
+        self.tick();
+    }
+    // a selected joypad line is low, which ends STOP
+    fn joypad_low(&self) -> bool {
+        true
+    }
+    // machine cycles the cpu is held for by a dma transfer
+    fn dma_stall(&mut self) -> u32 {
+        0
+    }
+}
+
+// 64KiB of plain RAM, for testing the cpu alone
+pub struct FlatBus {
+    memory: Vec<u8>,
+}
+impl FlatBus {
+    pub fn new() -> Self {
+        Self {
+            memory: vec![0; 0x10000],
+        }
+    }
+}
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Memory for FlatBus {
+    fn get(&self, index: u16) -> u8 {
+        self.memory[index as usize]
+    }
+    fn set(&mut self, index: u16, value: u8) {
+        self.memory[index as usize] = value;
+    }
+}
+impl Bus for FlatBus {}
+
+// records every access going through to the inner bus with the machine cycle it happened in
+pub struct RecordingBus<B: Bus> {
+    pub inner: B,
+    accesses: RefCell<Vec<(u32, u16, u8, Access)>>,
+    ticks: u32,
+}
+impl<B: Bus> RecordingBus<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            accesses: RefCell::new(vec![]),
+            ticks: 0,
+        }
+    }
+    pub fn take(&self) -> Vec<(u32, u16, u8, Access)> {
+        self.accesses.take()
+    }
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+}
+impl<B: Bus + Default> Default for RecordingBus<B> {
+    fn default() -> Self {
+        Self::new(B::default())
+    }
+}
+impl<B: Bus> Memory for RecordingBus<B> {
+    fn get(&self, index: u16) -> u8 {
+        let value = self.inner.get(index);
+        self.accesses
+            .borrow_mut()
+            .push((self.ticks, index, value, Access::Read));
+        value
+    }
+    fn set(&mut self, index: u16, value: u8) {
+        self.accesses
+            .borrow_mut()
+            .push((self.ticks, index, value, Access::Write));
+        self.inner.set(index, value);
+    }
+}
+impl<B: Bus> Bus for RecordingBus<B> {
+    fn tick(&mut self) {
+        self.ticks += 1;
+        self.inner.tick();
+    }
+    fn fetch(&self, index: u16) -> u8 {
+        let value = self.inner.fetch(index);
+        self.accesses
+            .borrow_mut()
+            .push((self.ticks, index, value, Access::Fetch));
+        value
+    }
+    fn peek(&self, index: u16) -> u8 {
+        self.inner.peek(index)
+    }
+    fn poke(&mut self, index: u16, value: u8) {
+        self.inner.poke(index, value);
+    }
+    fn double_speed(&self) -> bool {
+        self.inner.double_speed()
+    }
+    fn set_double_speed(&mut self, double_speed: bool) {
+        self.inner.set_double_speed(double_speed);
+    }
+    fn switch_speed(&mut self) -> bool {
+        self.inner.switch_speed()
+    }
+    fn reset_div(&mut self) {
+        self.inner.reset_div();
+    }
+    fn stall(&mut self) {
+        self.ticks += 1;
+        self.inner.stall();
+    }
+    fn joypad_low(&self) -> bool {
+        self.inner.joypad_low()
+    }
+    fn dma_stall(&mut self) -> u32 {
+        self.inner.dma_stall()
+    }
+}
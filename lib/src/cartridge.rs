@@ -77,10 +77,7 @@ pub trait Cartridge: Stable + Memory {
         result
     }
     fn gbc_flag(&self) -> bool {
-        match self.get(0x0143) {
-            0x80 | 0xC0 => true,
-            _ => false,
-        }
+        matches!(self.get(0x0143), 0x80 | 0xC0)
     }
     fn get_ram_size(&self) -> usize {
         get_ram_size(self.get(0x0149))
@@ -118,6 +115,22 @@ pub trait Cartridge: Stable + Memory {
             _ => panic!("unkown cartridge type"),
         }
     }
+    // bank mapped at 0x4000~0x7FFF
+    fn rom_bank(&self) -> usize {
+        1
+    }
+    // bank mapped at 0xA000~0xBFFF
+    fn ram_bank(&self) -> usize {
+        0
+    }
+    // the whole rom and ram, to look at them without going through the mbc
+    fn rom(&self) -> &[u8];
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
     fn save_status(&self) -> Vec<u8> {
         vec![]
     }
@@ -151,7 +164,11 @@ impl Default for RomOnly {
         Self { rom: vec![] }
     }
 }
-impl Cartridge for RomOnly {}
+impl Cartridge for RomOnly {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+}
 
 #[derive(serde::Deserialize, serde::Serialize)]
 enum MBC1Mode {
@@ -271,6 +288,21 @@ impl Stable for MBC1 {
     }
 }
 impl Cartridge for MBC1 {
+    fn rom_bank(&self) -> usize {
+        (self.get_rom_blank_index() & (self.max_rom_blank_bit_num - 1)) as usize
+    }
+    fn ram_bank(&self) -> usize {
+        self.get_ram_blank_index() as usize
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
     fn save_status(&self) -> Vec<u8> {
         let mut data = Vec::new();
         bincode::serialize_into(&mut data, &self).unwrap();
@@ -368,6 +400,18 @@ impl Stable for MBC2 {
     }
 }
 impl Cartridge for MBC2 {
+    fn rom_bank(&self) -> usize {
+        (self.rom_blank & (self.max_rom_blank_bit_num - 1)) as usize
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
     fn save_status(&self) -> Vec<u8> {
         let mut data = Vec::new();
         bincode::serialize_into(&mut data, &self).unwrap();
@@ -562,6 +606,22 @@ impl Stable for MBC3 {
     }
 }
 impl Cartridge for MBC3 {
+    fn rom_bank(&self) -> usize {
+        self.rom_blank as usize
+    }
+    // 0x08~0x0C select the rtc registers
+    fn ram_bank(&self) -> usize {
+        self.ram_blank as usize
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
     fn save_status(&self) -> Vec<u8> {
         let mut data = Vec::new();
         bincode::serialize_into(&mut data, &self).unwrap();
@@ -668,6 +728,21 @@ impl Stable for MBC5 {
     }
 }
 impl Cartridge for MBC5 {
+    fn rom_bank(&self) -> usize {
+        self.get_rom_blank_index() & (self.max_rom_blank_bit_num - 1)
+    }
+    fn ram_bank(&self) -> usize {
+        self.ram_blank as usize
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
     fn save_status(&self) -> Vec<u8> {
         let mut data = Vec::new();
         bincode::serialize_into(&mut data, &self).unwrap();
@@ -0,0 +1,138 @@
+// The palettes the GBC boot rom gives a GB cartridge, looked up from the title of a Nintendo
+// game or picked with the keys held during the logo. The tables are the ones of the boot rom.
+
+// the title checksums with a palette of their own, 65 and up are shared by several titles
+const TITLE_CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+const SHARED_CHECKSUMS: usize = 65;
+// the 4th letter of the title for a shared checksum, every 14 entries try the next title
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+// a palette for every checksum and letter, the low 5 bits are the entry in PALETTES and the
+// high 3 say which of its palettes the objects take
+const PALETTE_IDS: [u8; 94] = [
+    0x7C, 0x08, 0x12, 0xA3, 0xA2, 0x07, 0x87, 0x4B, 0x20, 0x12, 0x65, 0xA8, 0x16, 0xA9, 0x86, 0xB1,
+    0x68, 0xA0, 0x87, 0x66, 0x12, 0xA1, 0x30, 0x3C, 0x12, 0x85, 0x12, 0x64, 0x1B, 0x07, 0x06, 0x6F,
+    0x6E, 0x6E, 0xAE, 0xAF, 0x6F, 0xB2, 0xAF, 0xB2, 0xA8, 0xAB, 0x6F, 0xAF, 0x86, 0xAE, 0xA2, 0xA2,
+    0x12, 0xAF, 0x13, 0x12, 0xA1, 0x6E, 0xAF, 0xAF, 0xAD, 0x06, 0x4C, 0x6E, 0xAF, 0xAF, 0x12, 0x7C,
+    0xAC, 0xA8, 0x6A, 0x6E, 0x13, 0xA0, 0x2D, 0xA8, 0x2B, 0xAC, 0x64, 0xAC, 0x6D, 0x87, 0xBC, 0x60,
+    0xB4, 0x13, 0x72, 0x7C, 0xB5, 0xAE, 0xAE, 0x7C, 0x7C, 0x65, 0xA2, 0x6C, 0x64, 0x85,
+];
+// the keys held as JoyPadKey bits and the palette id they pick instead
+const KEY_PALETTE_IDS: [(u8, u8); 12] = [
+    (0x04, 0x12), // up
+    (0x14, 0xB0), // up + A
+    (0x24, 0x79), // up + B
+    (0x02, 0xB8), // left
+    (0x12, 0xAD), // left + A
+    (0x22, 0x16), // left + B
+    (0x08, 0x17), // down
+    (0x18, 0x07), // down + A
+    (0x28, 0xBA), // down + B
+    (0x01, 0x05), // right
+    (0x11, 0x7C), // right + A
+    (0x21, 0x13), // right + B
+];
+// the first color in COLORS of the OBJ0, OBJ1 and BG palettes
+const PALETTES: [[usize; 3]; 29] = [
+    [64, 88, 32],
+    [68, 16, 52],
+    [111, 0, 56],
+    [111, 16, 60],
+    [16, 16, 28],
+    [16, 88, 72],
+    [16, 88, 80],
+    [112, 88, 96],
+    [76, 91, 36],
+    [64, 112, 40],
+    [15, 15, 44],
+    [16, 92, 112],
+    [68, 88, 8],
+    [16, 0, 8],
+    [16, 112, 12],
+    [112, 12, 0],
+    [12, 112, 16],
+    [84, 112, 16],
+    [12, 112, 0],
+    [16, 12, 108],
+    [100, 12, 112],
+    [0, 112, 32],
+    [20, 20, 20],
+    [12, 112, 48],
+    [16, 12, 112],
+    [0, 0, 4],
+    [112, 12, 24],
+    [104, 104, 104],
+    [16, 112, 116],
+];
+// as BGR555, the palettes overlap
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB, 0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000, 0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000, 0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000, 0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, 0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF, 0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000, 0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120, 0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000, 0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF, 0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+pub type Palette = [u16; 4];
+
+// the palette id for the cartridge header, read gives the bytes at 0x0100~0x014F
+pub fn title_palette_id(read: impl Fn(u16) -> u8) -> u8 {
+    let nintendo = match read(0x014B) {
+        0x33 => read(0x0144) == b'0' && read(0x0145) == b'1',
+        licensee => licensee == 0x01,
+    };
+    if !nintendo {
+        return PALETTE_IDS[0];
+    }
+    let checksum = (0x0134..=0x0143).fold(0u8, |sum, index| sum.wrapping_add(read(index)));
+    let index = match TITLE_CHECKSUMS.iter().position(|&value| value == checksum) {
+        Some(index) => index,
+        None => return PALETTE_IDS[0],
+    };
+    if index < SHARED_CHECKSUMS {
+        return PALETTE_IDS[index];
+    }
+    let letter = read(0x0137);
+    (index..PALETTE_IDS.len())
+        .step_by(14)
+        .find(|&index| FOURTH_LETTERS[index - SHARED_CHECKSUMS] == letter)
+        .map_or(PALETTE_IDS[0], |index| PALETTE_IDS[index])
+}
+
+// the palette id for the keys held, as JoyPadKey bits, None if they don't pick any
+pub fn key_palette_id(keys: u8) -> Option<u8> {
+    KEY_PALETTE_IDS
+        .iter()
+        .find(|(combination, _)| *combination == keys)
+        .map(|(_, id)| *id)
+}
+
+// the BG, OBJ0 and OBJ1 palettes of an id
+pub fn palettes(id: u8) -> [Palette; 3] {
+    let [obj0, obj1, bg] = PALETTES[(id & 0x1F) as usize];
+    let flags = id >> 5;
+    let obj1 = if flags & 0b100 != 0 {
+        obj1
+    } else if flags & 0b010 != 0 {
+        obj0
+    } else {
+        bg
+    };
+    let obj0 = if flags & 0b001 != 0 { obj0 } else { bg };
+    let palette = |start: usize| {
+        let mut palette = [0; 4];
+        palette.copy_from_slice(&COLORS[start..start + 4]);
+        palette
+    };
+    [palette(bg), palette(obj0), palette(obj1)]
+}
@@ -1,13 +1,15 @@
-use crate::gameboy_mode::GameBoyMode;
-use crate::memory::Memory;
-use crate::mmu::{HDMAMode, Mmu};
+use crate::bus::Bus;
+use crate::gameboy_mode::{GameBoyMode, Model};
 use crate::util::{check_bit, u16_from_2u8, u8u8_from_u16};
 // use log::info;
-use std::{cell::RefCell, rc::Rc};
+use std::io::Write;
 use Flag::{C, H, N, Z};
 
+// machine cycles the cpu is paused for by a speed switch
+const SPEED_SWITCH_CYCLES: u32 = 2050;
+
 //  0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
-const OP_CYCLES: [u32; 256] = [
+pub(crate) const OP_CYCLES: [u32; 256] = [
     1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, // 0
     1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 1
     2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 2
@@ -27,7 +29,7 @@ const OP_CYCLES: [u32; 256] = [
 ];
 
 //  0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
-const CB_CYCLES: [u32; 256] = [
+pub(crate) const CB_CYCLES: [u32; 256] = [
     2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 0
     2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 1
     2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 2
@@ -53,47 +55,69 @@ pub enum Flag {
     C = 0b0001_0000,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
-struct Registers {
-    a: u8,
-    b: u8,
-    c: u8,
-    d: u8,
-    e: u8,
-    f: u8,
-    h: u8,
-    l: u8,
-    sp: u16,
-    pc: u16,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Registers {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub f: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
 }
 impl Registers {
-    fn new(mode: GameBoyMode) -> Self {
-        if mode == GameBoyMode::GBC {
-            Registers {
-                a: 0x11,
-                b: 0,
-                c: 0,
-                d: 0xFF,
-                e: 0x56,
-                f: 0x80,
-                h: 0,
-                l: 0x0D,
-                sp: 0,
-                pc: 0,
-            }
-        } else {
-            Registers {
-                a: 0x01,
-                b: 0,
-                c: 0x13,
-                d: 0x00,
-                e: 0xD8,
-                f: 0x00,
-                h: 0x01,
-                l: 0x4D,
-                sp: 0,
-                pc: 0,
+    // what the boot rom of model leaves for the cartridge, header covers 0x0000~0x014F
+    pub(crate) fn new(model: Model, header: &[u8]) -> Self {
+        let header_checksum = header[0x014D];
+        let title_checksum = header[0x0134..=0x0143]
+            .iter()
+            .fold(0u8, |sum, &x| sum.wrapping_add(x));
+        // the GBC boot rom only looks the title up for Nintendo's own games
+        let nintendo =
+            header[0x014B] == 0x01 || (header[0x014B] == 0x33 && &header[0x0144..=0x0145] == b"01");
+        let gbc_flag = matches!(header[0x0143], 0x80 | 0xC0);
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::DMG0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            Model::DMG | Model::MGB => {
+                let a = if model == Model::DMG { 0x01 } else { 0xFF };
+                // H and C are left by the header check
+                let f = if header_checksum == 0 { 0x80 } else { 0xB0 };
+                (a, f, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D)
+            }
+            Model::SGB => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::SGB2 => (0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::CGB | Model::AGB => {
+                let (b, d, e, h, l) = if gbc_flag {
+                    (0x00, 0xFF, 0x56, 0x00, 0x0D)
+                } else if nintendo {
+                    (title_checksum, 0x00, 0x08, 0x99, 0x1A)
+                } else {
+                    (0x00, 0x00, 0x08, 0x00, 0x7C)
+                };
+                if model == Model::CGB {
+                    (0x11, 0x80, b, 0x00, d, e, h, l)
+                } else {
+                    // the AGB boot rom ends with INC B
+                    let b = b.wrapping_add(1);
+                    let f = ((b == 0) as u8) << 7 | ((b & 0x0F == 0) as u8) << 5;
+                    (0x11, f, b, 0x00, d, e, h, l)
+                }
             }
+        };
+        Registers {
+            a,
+            b,
+            c,
+            d,
+            e,
+            f,
+            h,
+            l,
+            sp: 0xFFFE,
+            pc: 0x0100,
         }
     }
     fn set_af(&mut self, value: u16) {
@@ -101,7 +125,7 @@ impl Registers {
         self.a = value_high;
         self.f = value_low & 0xF0;
     }
-    fn get_af(&self) -> u16 {
+    pub fn get_af(&self) -> u16 {
         u16_from_2u8(self.f, self.a)
     }
     fn set_bc(&mut self, value: u16) {
@@ -109,7 +133,7 @@ impl Registers {
         self.b = value_high;
         self.c = value_low;
     }
-    fn get_bc(&self) -> u16 {
+    pub fn get_bc(&self) -> u16 {
         u16_from_2u8(self.c, self.b)
     }
     fn set_de(&mut self, value: u16) {
@@ -117,7 +141,7 @@ impl Registers {
         self.d = value_high;
         self.e = value_low;
     }
-    fn get_de(&self) -> u16 {
+    pub fn get_de(&self) -> u16 {
         u16_from_2u8(self.e, self.d)
     }
     fn set_hl(&mut self, value: u16) {
@@ -125,7 +149,7 @@ impl Registers {
         self.h = value_high;
         self.l = value_low;
     }
-    fn get_hl(&self) -> u16 {
+    pub fn get_hl(&self) -> u16 {
         u16_from_2u8(self.l, self.h)
     }
     fn get_flag(&self, flag: Flag) -> bool {
@@ -140,50 +164,90 @@ impl Registers {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuState {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+    // IME after the next instruction, set by EI and RETI
+    pub ime_next: Option<bool>,
+    pub halted: bool,
+    pub double_speed: bool,
+}
+
+// an opcode that doesn't exist locks the cpu up until the next reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Lockup {
+    pub opcode: u8,
+    pub address: u16,
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
-pub struct Cpu {
+pub struct Cpu<B> {
     mode: GameBoyMode,
     cycles: u32,
     cur_opcode_cycles: u32,
     ime: bool, // true:enable; false:disable
     ime_next: Option<bool>,
     is_halted: bool,
-    reg: Registers,
+    // the byte after HALT is fetched twice
+    halt_bug: bool,
+    is_stopped: bool,
+    lockup: Option<Lockup>,
+    // reported to the host once
     #[serde(skip)]
-    pub mmu: Rc<RefCell<Mmu>>,
+    new_lockup: Option<Lockup>,
+    reg: Registers,
+    pub bus: B,
     step_flip: bool,
+    // machine cycles taken by the current step
+    #[serde(skip)]
+    ticks: u32,
 
-    is_hblank: bool,
+    #[serde(skip)]
+    trace: Option<Box<dyn Write>>,
 }
 
-impl Cpu {
-    pub fn new(mmu: Rc<RefCell<Mmu>>, skip_bios: bool) -> Self {
-        let mode = mmu.borrow().mode;
-        let reg = Registers::new(mode);
-        let mut cpu = Cpu {
+impl<B: Bus> Cpu<B> {
+    pub fn new(model: Model, bus: B, skip_bios: bool) -> Self {
+        let mode = if model.is_gbc() {
+            GameBoyMode::GBC
+        } else {
+            GameBoyMode::GB
+        };
+        // the boot rom sets them up when it runs
+        let reg = if skip_bios {
+            let header: Vec<u8> = (0..0x0150).map(|index| bus.peek(index)).collect();
+            Registers::new(model, &header)
+        } else {
+            Registers::default()
+        };
+        Cpu {
             mode,
             reg,
-            mmu: mmu,
+            bus,
             cycles: 0,
             cur_opcode_cycles: 0,
             ime: false,
             ime_next: None,
             is_halted: false,
+            halt_bug: false,
+            is_stopped: false,
+            lockup: None,
+            new_lockup: None,
             step_flip: false,
-            is_hblank: false,
-        };
-        if skip_bios {
-            cpu.skip_bios();
+            ticks: 0,
+            trace: None,
         }
-        cpu
-    }
-    pub fn skip_bios(&mut self) {
-        self.reg.pc = 0x0100;
-        self.reg.sp = 0xFFFE;
     }
+    // IE and IF are polled internally, not over the bus
     fn interrupt_check_pending(&mut self) -> u8 {
-        let m_ie = self.mmu.borrow().get(0xFFFF);
-        let m_if = self.mmu.borrow().get(0xFF0F);
+        let m_ie = self.bus.peek(0xFFFF);
+        let m_if = self.bus.peek(0xFF0F);
         let m_r = m_ie & m_if;
         m_r & 0x1F
     }
@@ -200,9 +264,10 @@ impl Cpu {
                     _ => panic!("index is out of range"),
                 };
                 self.ime = false;
-                let m_if = self.mmu.borrow().get(0xFF0F);
+                self.tick();
+                let m_if = self.bus.peek(0xFF0F);
                 let m_if = self.opc_res(index, m_if);
-                self.mmu.borrow_mut().set(0xFF0F, m_if);
+                self.bus.poke(0xFF0F, m_if);
 
                 let a16 = address;
                 let pc = self.reg.pc;
@@ -214,9 +279,18 @@ impl Cpu {
         return 0;
     }
     fn step(&mut self) -> u32 {
+        self.ticks = 0;
+        let cycles = self.step_cycles();
+        // the internal cycles at the end of the step
+        while self.ticks < cycles {
+            self.tick();
+        }
+        self.ticks * 4
+    }
+    fn step_cycles(&mut self) -> u32 {
         if self.mode == GameBoyMode::GBC {
-            let length = self.step_hdma();
-            let length = if self.mmu.borrow().speed.current_speed {
+            let length = self.bus.dma_stall();
+            let length = if self.bus.double_speed() {
                 2 * length
             } else {
                 1 * length
@@ -225,72 +299,30 @@ impl Cpu {
                 return length;
             }
         }
-        let interrupts = self.interrupt_check_pending();
-        if self.is_halted {
-            self.step_halt(interrupts) * 4
-        } else {
-            self.step_run(interrupts) * 4
-        }
-    }
-    fn step_hdma(&mut self) -> u32 {
-        let mut mmu_mut = self.mmu.borrow_mut();
-        let active = mmu_mut.hdma.active;
-        if !active {
-            return 0;
+        // the rest of the machine keeps running
+        if self.lockup.is_some() {
+            return 1;
         }
-        let mode = &mmu_mut.hdma.mode;
-        match mode {
-            HDMAMode::GeneralPurposeDMA => {
-                if active {
-                    let source = mmu_mut.hdma.source;
-                    let destination = mmu_mut.hdma.destination;
-                    let remain = mmu_mut.hdma.remain as u16;
-                    for index in 0x0000..remain {
-                        let s = source + index;
-                        let d = destination + index;
-                        let s_v = mmu_mut.get(s);
-                        mmu_mut.set(d, s_v);
-                    }
-                    mmu_mut.hdma.active = false;
-                    return (remain / 0x10 * 8) as u32;
-                }
-            }
-            HDMAMode::HBlankDMA => {
-                let mode_flag = mmu_mut.ppu.stat.mode_flag as u8;
-                if mode_flag == 0 {
-                    if !self.is_hblank {
-                        self.is_hblank = true;
-                        if active {
-                            let source = mmu_mut.hdma.source;
-                            let destination = mmu_mut.hdma.destination;
-                            let remain = mmu_mut.hdma.remain;
-                            for index in 0x0000..0x10 {
-                                let s = source + index;
-                                let d = destination + index;
-                                let s_v = mmu_mut.get(s);
-                                mmu_mut.set(d, s_v);
-                            }
-                            mmu_mut.hdma.source = source + 0x10;
-                            mmu_mut.hdma.destination = destination + 0x10;
-                            mmu_mut.hdma.remain = remain - 0x10;
-                            if remain - 0x10 == 0 {
-                                mmu_mut.hdma.active = false;
-                            }
-                            return 8 as u32;
-                        }
-                    }
-                } else {
-                    self.is_hblank = false;
-                }
+        if self.is_stopped {
+            if self.bus.joypad_low() {
+                self.is_stopped = false;
             }
+            return 1;
+        }
+        if self.is_halted {
+            let interrupts = self.interrupt_check_pending();
+            self.step_halt(interrupts)
+        } else {
+            self.step_run()
         }
-        0
     }
     fn step_halt(&mut self, interrupts: u8) -> u32 {
         if self.ime {
             if interrupts > 0 {
                 self.is_halted = false;
-                self.interrupt_handle(interrupts)
+                // waking up takes a machine cycle before the dispatch
+                self.tick();
+                self.interrupt_handle(interrupts) + 1
             } else {
                 1
             }
@@ -303,23 +335,96 @@ impl Cpu {
             }
         }
     }
-    fn step_run(&mut self, interrupts: u8) -> u32 {
+    fn step_run(&mut self) -> u32 {
         let ime_next = self.ime_next.clone();
-        let mut cycles = if self.ime && interrupts > 0 {
-            self.interrupt_handle(interrupts)
-        } else {
-            0
-        };
-        if cycles == 0 {
-            let opcode = self.imm();
-            cycles = self.run_opcode(opcode);
-            if let Some(ime) = ime_next {
-                self.ime = ime;
-                self.ime_next = None;
-            }
-        };
+        // interrupts requested up to the opcode fetch cycle take its place
+        self.tick();
+        let interrupts = self.interrupt_check_pending();
+        if self.ime && interrupts > 0 {
+            return self.interrupt_handle(interrupts);
+        }
+        if self.trace.is_some() {
+            self.write_trace();
+        }
+        let opcode = self.bus.fetch(self.reg.pc);
+        self.reg.pc = self.reg.pc.wrapping_add(1);
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.reg.pc = self.reg.pc.wrapping_sub(1);
+        }
+        let cycles = self.run_opcode(opcode);
+        // a DI right after EI takes the pending enable back
+        if let (Some(ime), Some(_)) = (ime_next, self.ime_next) {
+            self.ime = ime;
+            self.ime_next = None;
+        }
         cycles
     }
+    pub fn registers(&self) -> Registers {
+        self.reg
+    }
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+    pub fn lockup(&self) -> Option<Lockup> {
+        self.lockup
+    }
+    pub fn take_lockup(&mut self) -> Option<Lockup> {
+        self.new_lockup.take()
+    }
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            af: self.reg.get_af(),
+            bc: self.reg.get_bc(),
+            de: self.reg.get_de(),
+            hl: self.reg.get_hl(),
+            sp: self.reg.sp,
+            pc: self.reg.pc,
+            ime: self.ime,
+            ime_next: self.ime_next,
+            halted: self.is_halted,
+            double_speed: self.bus.double_speed(),
+        }
+    }
+    // the low bits of F are dropped like POP AF does
+    pub fn set_state(&mut self, state: CpuState) {
+        self.reg.set_af(state.af);
+        self.reg.set_bc(state.bc);
+        self.reg.set_de(state.de);
+        self.reg.set_hl(state.hl);
+        self.reg.sp = state.sp;
+        self.reg.pc = state.pc;
+        self.ime = state.ime;
+        self.ime_next = state.ime_next;
+        self.is_halted = state.halted;
+        self.bus.set_double_speed(state.double_speed);
+    }
+    // the next trick starts a new step
+    pub fn is_instruction_boundary(&self) -> bool {
+        self.cycles == 0
+    }
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.trace = trace;
+    }
+    // gameboy-doctor log line
+    fn write_trace(&mut self) {
+        let reg = &self.reg;
+        let pc = reg.pc;
+        let pcmem = [
+            self.bus.peek(pc),
+            self.bus.peek(pc.wrapping_add(1)),
+            self.bus.peek(pc.wrapping_add(2)),
+            self.bus.peek(pc.wrapping_add(3)),
+        ];
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = writeln!(
+                trace,
+                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                reg.a, reg.f, reg.b, reg.c, reg.d, reg.e, reg.h, reg.l, reg.sp, pc,
+                pcmem[0], pcmem[1], pcmem[2], pcmem[3]
+            );
+        }
+    }
     pub fn flip(&mut self) -> bool {
         let r = self.step_flip;
         if r {
@@ -334,7 +439,7 @@ impl Cpu {
         if self.cycles == 0 {
             self.step_flip = true;
             self.cur_opcode_cycles = self.step();
-            if self.mode == GameBoyMode::GBC && self.mmu.borrow().speed.current_speed {
+            if self.mode == GameBoyMode::GBC && self.bus.double_speed() {
                 self.cur_opcode_cycles = self.cur_opcode_cycles / 2;
             }
         }
@@ -391,7 +496,7 @@ impl Cpu {
                 0x85 => self.opc_add(self.reg.l),
                 0x86 => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     self.opc_add(hl_v);
                 }
                 0x87 => self.opc_add(self.reg.a),
@@ -425,7 +530,7 @@ impl Cpu {
                 0x8D => self.opc_adc(self.reg.l),
                 0x8E => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     self.opc_adc(hl_v);
                 }
                 0x8F => self.opc_adc(self.reg.a),
@@ -448,9 +553,9 @@ impl Cpu {
                     0x05 => self.reg.l = self.opc_rlc(self.reg.l),
                     0x06 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_rlc(hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x07 => self.reg.a = self.opc_rlc(self.reg.a),
 
@@ -462,9 +567,9 @@ impl Cpu {
                     0x0D => self.reg.l = self.opc_rrc(self.reg.l),
                     0x0E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_rrc(hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x0F => self.reg.a = self.opc_rrc(self.reg.a),
 
@@ -476,9 +581,9 @@ impl Cpu {
                     0x15 => self.reg.l = self.opc_rl(self.reg.l),
                     0x16 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_rl(hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x17 => self.reg.a = self.opc_rl(self.reg.a),
 
@@ -490,9 +595,9 @@ impl Cpu {
                     0x1D => self.reg.l = self.opc_rr(self.reg.l),
                     0x1E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_rr(hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x1F => self.reg.a = self.opc_rr(self.reg.a),
 
@@ -504,9 +609,9 @@ impl Cpu {
                     0x25 => self.reg.l = self.opc_sla(self.reg.l),
                     0x26 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_sla(hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x27 => self.reg.a = self.opc_sla(self.reg.a),
 
@@ -518,9 +623,9 @@ impl Cpu {
                     0x2D => self.reg.l = self.opc_sra(self.reg.l),
                     0x2E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_sra(hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x2F => self.reg.a = self.opc_sra(self.reg.a),
 
@@ -532,9 +637,9 @@ impl Cpu {
                     0x35 => self.reg.l = self.opc_swap(self.reg.l),
                     0x36 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_swap(hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x37 => self.reg.a = self.opc_swap(self.reg.a),
 
@@ -546,9 +651,9 @@ impl Cpu {
                     0x3D => self.reg.l = self.opc_srl(self.reg.l),
                     0x3E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_srl(hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x3F => self.reg.a = self.opc_srl(self.reg.a),
 
@@ -560,7 +665,7 @@ impl Cpu {
                     0x45 => self.opc_bit(0, self.reg.l),
                     0x46 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         self.opc_bit(0, hl_v);
                     }
                     0x47 => self.opc_bit(0, self.reg.a),
@@ -573,7 +678,7 @@ impl Cpu {
                     0x4D => self.opc_bit(1, self.reg.l),
                     0x4E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         self.opc_bit(1, hl_v);
                     }
                     0x4F => self.opc_bit(1, self.reg.a),
@@ -586,7 +691,7 @@ impl Cpu {
                     0x55 => self.opc_bit(2, self.reg.l),
                     0x56 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         self.opc_bit(2, hl_v);
                     }
                     0x57 => self.opc_bit(2, self.reg.a),
@@ -599,7 +704,7 @@ impl Cpu {
                     0x5D => self.opc_bit(3, self.reg.l),
                     0x5E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         self.opc_bit(3, hl_v);
                     }
                     0x5F => self.opc_bit(3, self.reg.a),
@@ -612,7 +717,7 @@ impl Cpu {
                     0x65 => self.opc_bit(4, self.reg.l),
                     0x66 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         self.opc_bit(4, hl_v);
                     }
                     0x67 => self.opc_bit(4, self.reg.a),
@@ -625,7 +730,7 @@ impl Cpu {
                     0x6D => self.opc_bit(5, self.reg.l),
                     0x6E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         self.opc_bit(5, hl_v);
                     }
                     0x6F => self.opc_bit(5, self.reg.a),
@@ -638,7 +743,7 @@ impl Cpu {
                     0x75 => self.opc_bit(6, self.reg.l),
                     0x76 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         self.opc_bit(6, hl_v);
                     }
                     0x77 => self.opc_bit(6, self.reg.a),
@@ -651,7 +756,7 @@ impl Cpu {
                     0x7D => self.opc_bit(7, self.reg.l),
                     0x7E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         self.opc_bit(7, hl_v);
                     }
                     0x7F => self.opc_bit(7, self.reg.a),
@@ -664,9 +769,9 @@ impl Cpu {
                     0x85 => self.reg.l = self.opc_res(0, self.reg.l),
                     0x86 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_res(0, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x87 => self.reg.a = self.opc_res(0, self.reg.a),
 
@@ -678,9 +783,9 @@ impl Cpu {
                     0x8D => self.reg.l = self.opc_res(1, self.reg.l),
                     0x8E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_res(1, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x8F => self.reg.a = self.opc_res(1, self.reg.a),
 
@@ -692,9 +797,9 @@ impl Cpu {
                     0x95 => self.reg.l = self.opc_res(2, self.reg.l),
                     0x96 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_res(2, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x97 => self.reg.a = self.opc_res(2, self.reg.a),
 
@@ -706,9 +811,9 @@ impl Cpu {
                     0x9D => self.reg.l = self.opc_res(3, self.reg.l),
                     0x9E => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_res(3, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0x9F => self.reg.a = self.opc_res(3, self.reg.a),
 
@@ -720,9 +825,9 @@ impl Cpu {
                     0xA5 => self.reg.l = self.opc_res(4, self.reg.l),
                     0xA6 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_res(4, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xA7 => self.reg.a = self.opc_res(4, self.reg.a),
 
@@ -734,9 +839,9 @@ impl Cpu {
                     0xAD => self.reg.l = self.opc_res(5, self.reg.l),
                     0xAE => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_res(5, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xAF => self.reg.a = self.opc_res(5, self.reg.a),
 
@@ -748,9 +853,9 @@ impl Cpu {
                     0xB5 => self.reg.l = self.opc_res(6, self.reg.l),
                     0xB6 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_res(6, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xB7 => self.reg.a = self.opc_res(6, self.reg.a),
 
@@ -762,9 +867,9 @@ impl Cpu {
                     0xBD => self.reg.l = self.opc_res(7, self.reg.l),
                     0xBE => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_res(7, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xBF => self.reg.a = self.opc_res(7, self.reg.a),
 
@@ -776,9 +881,9 @@ impl Cpu {
                     0xC5 => self.reg.l = self.opc_set(0, self.reg.l),
                     0xC6 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_set(0, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xC7 => self.reg.a = self.opc_set(0, self.reg.a),
 
@@ -790,9 +895,9 @@ impl Cpu {
                     0xCD => self.reg.l = self.opc_set(1, self.reg.l),
                     0xCE => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_set(1, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xCF => self.reg.a = self.opc_set(1, self.reg.a),
 
@@ -804,9 +909,9 @@ impl Cpu {
                     0xD5 => self.reg.l = self.opc_set(2, self.reg.l),
                     0xD6 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_set(2, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xD7 => self.reg.a = self.opc_set(2, self.reg.a),
 
@@ -818,9 +923,9 @@ impl Cpu {
                     0xDD => self.reg.l = self.opc_set(3, self.reg.l),
                     0xDE => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_set(3, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xDF => self.reg.a = self.opc_set(3, self.reg.a),
 
@@ -832,9 +937,9 @@ impl Cpu {
                     0xE5 => self.reg.l = self.opc_set(4, self.reg.l),
                     0xE6 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_set(4, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xE7 => self.reg.a = self.opc_set(4, self.reg.a),
 
@@ -846,9 +951,9 @@ impl Cpu {
                     0xED => self.reg.l = self.opc_set(5, self.reg.l),
                     0xEE => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_set(5, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xEF => self.reg.a = self.opc_set(5, self.reg.a),
 
@@ -860,9 +965,9 @@ impl Cpu {
                     0xF5 => self.reg.l = self.opc_set(6, self.reg.l),
                     0xF6 => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_set(6, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xF7 => self.reg.a = self.opc_set(6, self.reg.a),
 
@@ -874,9 +979,9 @@ impl Cpu {
                     0xFD => self.reg.l = self.opc_set(7, self.reg.l),
                     0xFE => {
                         let hl = self.reg.get_hl();
-                        let hl_v = self.mmu.borrow().get(hl);
+                        let hl_v = self.read(hl);
                         let r = self.opc_set(7, hl_v);
-                        self.mmu.borrow_mut().set(hl, r);
+                        self.write(hl, r);
                     }
                     0xFF => self.reg.a = self.opc_set(7, self.reg.a),
                 }
@@ -916,7 +1021,7 @@ impl Cpu {
                 0xBD => self.opc_cp(self.reg.l),
                 0xBE => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     self.opc_cp(hl_v);
                 }
                 0xBF => self.opc_cp(self.reg.a),
@@ -938,9 +1043,9 @@ impl Cpu {
                 0x2D => self.reg.l = self.opc_dec(self.reg.l),
                 0x35 => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     let new_hl_v = self.opc_dec(hl_v);
-                    self.mmu.borrow_mut().set(hl, new_hl_v);
+                    self.write(hl, new_hl_v);
                 }
                 _ => {}
             },
@@ -972,9 +1077,9 @@ impl Cpu {
                 0x2C => self.reg.l = self.opc_inc(self.reg.l),
                 0x34 => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     let new_hl_v = self.opc_inc(hl_v);
-                    self.mmu.borrow_mut().set(hl, new_hl_v);
+                    self.write(hl, new_hl_v);
                 }
                 _ => {}
             },
@@ -1047,33 +1152,33 @@ impl Cpu {
                     }
                 };
                 let a = self.reg.a;
-                self.mmu.borrow_mut().set(address, a);
+                self.write(address, a);
             }
             // LD A,(address)
             0x1a => {
                 let de = self.reg.get_de();
-                let de_v = self.mmu.borrow_mut().get(de);
+                let de_v = self.read(de);
                 self.reg.a = de_v;
             }
             0xF2 => {
                 let c = self.reg.c;
                 let address = 0xFF00 | (c as u16);
-                let address_v = self.mmu.borrow_mut().get(address);
+                let address_v = self.read(address);
                 self.reg.a = address_v;
             }
             0x0a => {
                 let bc = self.reg.get_bc();
-                let bc_v = self.mmu.borrow_mut().get(bc);
+                let bc_v = self.read(bc);
                 self.reg.a = bc_v;
             }
             0xF0 => {
                 let address = 0xFF00 | (self.imm() as u16);
-                let value = self.mmu.borrow_mut().get(address);
+                let value = self.read(address);
                 self.reg.a = value;
             }
             0xFA => {
                 let a16 = self.imm_word();
-                let a16_v = self.mmu.borrow().get(a16);
+                let a16_v = self.read(a16);
                 self.reg.a = a16_v;
             }
             // LD r,r
@@ -1086,7 +1191,7 @@ impl Cpu {
                 0x45 => self.reg.b = self.reg.l,
                 0x46 => {
                     let hl = self.reg.get_hl();
-                    self.reg.b = self.mmu.borrow().get(hl);
+                    self.reg.b = self.read(hl);
                 }
                 0x47 => self.reg.b = self.reg.a,
                 0x48 => self.reg.c = self.reg.b,
@@ -1097,7 +1202,7 @@ impl Cpu {
                 0x4D => self.reg.c = self.reg.l,
                 0x4E => {
                     let hl = self.reg.get_hl();
-                    self.reg.c = self.mmu.borrow().get(hl);
+                    self.reg.c = self.read(hl);
                 }
                 0x4F => self.reg.c = self.reg.a,
                 0x50 => self.reg.d = self.reg.b,
@@ -1108,7 +1213,7 @@ impl Cpu {
                 0x55 => self.reg.d = self.reg.l,
                 0x56 => {
                     let hl = self.reg.get_hl();
-                    self.reg.d = self.mmu.borrow().get(hl);
+                    self.reg.d = self.read(hl);
                 }
                 0x57 => self.reg.d = self.reg.a,
                 0x58 => self.reg.e = self.reg.b,
@@ -1119,7 +1224,7 @@ impl Cpu {
                 0x5D => self.reg.e = self.reg.l,
                 0x5E => {
                     let hl = self.reg.get_hl();
-                    self.reg.e = self.mmu.borrow().get(hl);
+                    self.reg.e = self.read(hl);
                 }
                 0x5F => self.reg.e = self.reg.a,
                 0x60 => self.reg.h = self.reg.b,
@@ -1130,7 +1235,7 @@ impl Cpu {
                 0x65 => self.reg.h = self.reg.l,
                 0x66 => {
                     let hl = self.reg.get_hl();
-                    self.reg.h = self.mmu.borrow().get(hl);
+                    self.reg.h = self.read(hl);
                 }
                 0x67 => self.reg.h = self.reg.a,
                 0x68 => self.reg.l = self.reg.b,
@@ -1141,36 +1246,36 @@ impl Cpu {
                 0x6D => self.reg.l = self.reg.l,
                 0x6E => {
                     let hl = self.reg.get_hl();
-                    self.reg.l = self.mmu.borrow().get(hl);
+                    self.reg.l = self.read(hl);
                 }
                 0x6F => self.reg.l = self.reg.a,
                 0x70 => {
                     let hl = self.reg.get_hl();
-                    self.mmu.borrow_mut().set(hl, self.reg.b);
+                    self.write(hl, self.reg.b);
                 }
                 0x71 => {
                     let hl = self.reg.get_hl();
-                    self.mmu.borrow_mut().set(hl, self.reg.c);
+                    self.write(hl, self.reg.c);
                 }
                 0x72 => {
                     let hl = self.reg.get_hl();
-                    self.mmu.borrow_mut().set(hl, self.reg.d);
+                    self.write(hl, self.reg.d);
                 }
                 0x73 => {
                     let hl = self.reg.get_hl();
-                    self.mmu.borrow_mut().set(hl, self.reg.e);
+                    self.write(hl, self.reg.e);
                 }
                 0x74 => {
                     let hl = self.reg.get_hl();
-                    self.mmu.borrow_mut().set(hl, self.reg.h);
+                    self.write(hl, self.reg.h);
                 }
                 0x75 => {
                     let hl = self.reg.get_hl();
-                    self.mmu.borrow_mut().set(hl, self.reg.l);
+                    self.write(hl, self.reg.l);
                 }
                 0x77 => {
                     let hl = self.reg.get_hl();
-                    self.mmu.borrow_mut().set(hl, self.reg.a);
+                    self.write(hl, self.reg.a);
                 }
                 0x78 => self.reg.a = self.reg.b,
                 0x79 => self.reg.a = self.reg.c,
@@ -1180,7 +1285,7 @@ impl Cpu {
                 0x7D => self.reg.a = self.reg.l,
                 0x7E => {
                     let hl = self.reg.get_hl();
-                    self.reg.a = self.mmu.borrow().get(hl);
+                    self.reg.a = self.read(hl);
                 }
                 0x7F => self.reg.a = self.reg.a,
                 _ => {}
@@ -1198,7 +1303,7 @@ impl Cpu {
                     0x2E => self.reg.l = d8,
                     0x36 => {
                         let hl = self.reg.get_hl();
-                        self.mmu.borrow_mut().set(hl, d8);
+                        self.write(hl, d8);
                     }
                     _ => {}
                 }
@@ -1221,32 +1326,32 @@ impl Cpu {
             // LD (a16) SP
             0x08 => {
                 let a16 = self.imm_word();
-                self.mmu.borrow_mut().set_word(a16, self.reg.sp);
+                self.write_word(a16, self.reg.sp);
             }
             // LD (HL+),A
             0x22 => {
                 let hl = self.reg.get_hl();
                 let a = self.reg.a;
-                self.mmu.borrow_mut().set(hl, a);
+                self.write(hl, a);
                 self.reg.set_hl(hl.wrapping_add(1));
             }
             // LD (HL-),A
             0x32 => {
                 let hl = self.reg.get_hl();
                 let a = self.reg.a;
-                self.mmu.borrow_mut().set(hl, a);
+                self.write(hl, a);
                 self.reg.set_hl(hl.wrapping_sub(1));
             }
             // LD A,(HL+)
             0x2A => {
                 let hl = self.reg.get_hl();
-                self.reg.a = self.mmu.borrow().get(hl);
+                self.reg.a = self.read(hl);
                 self.reg.set_hl(hl.wrapping_add(1));
             }
             // LD A,(HL-)
             0x3A => {
                 let hl = self.reg.get_hl();
-                self.reg.a = self.mmu.borrow().get(hl);
+                self.reg.a = self.read(hl);
                 self.reg.set_hl(hl.wrapping_sub(1));
             }
             // LD HL,SP+r8
@@ -1306,6 +1411,7 @@ impl Cpu {
                         panic!("RET f. But cond?")
                     }
                 };
+                self.tick();
                 if is_jump {
                     let pc = self.stack_pop();
                     self.reg.pc = pc;
@@ -1346,7 +1452,7 @@ impl Cpu {
                 0x95 => self.opc_sub(self.reg.l),
                 0x96 => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     self.opc_sub(hl_v);
                 }
                 0x97 => self.opc_sub(self.reg.a),
@@ -1366,7 +1472,7 @@ impl Cpu {
                 0x9D => self.opc_sbc(self.reg.l),
                 0x9E => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     self.opc_sbc(hl_v);
                 }
                 0x9F => self.opc_sbc(self.reg.a),
@@ -1387,7 +1493,7 @@ impl Cpu {
                 0xA5 => self.opc_and(self.reg.l),
                 0xA6 => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     self.opc_and(hl_v);
                 }
                 0xA7 => self.opc_and(self.reg.a),
@@ -1407,7 +1513,7 @@ impl Cpu {
                 0xAD => self.opc_xor(self.reg.l),
                 0xAE => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     self.opc_xor(hl_v);
                 }
                 0xAF => self.opc_xor(self.reg.a),
@@ -1428,7 +1534,7 @@ impl Cpu {
                 0xB5 => self.opc_or(self.reg.l),
                 0xB6 => {
                     let hl = self.reg.get_hl();
-                    let hl_v = self.mmu.borrow().get(hl);
+                    let hl_v = self.read(hl);
                     self.opc_or(hl_v);
                 }
                 0xB7 => self.opc_or(self.reg.a),
@@ -1441,9 +1547,16 @@ impl Cpu {
             // STOP 0
             0x10 => {
                 let _ = self.imm();
-                self.mmu.borrow_mut().speed.switch();
-                // TODO
-                // Halt CPU & LCD display until button pressed
+                self.bus.reset_div();
+                if self.mode == GameBoyMode::GBC && self.bus.switch_speed() {
+                    // the cpu waits for the clock to settle, DIV doesn't run meanwhile
+                    for _ in 0..SPEED_SWITCH_CYCLES {
+                        self.ticks += 1;
+                        self.bus.stall();
+                    }
+                } else {
+                    self.is_stopped = true;
+                }
             }
             // DAA
             0x27 => {
@@ -1500,7 +1613,15 @@ impl Cpu {
             }
             // HALT
             0x76 => {
-                self.is_halted = true;
+                let pending = self.interrupt_check_pending() > 0;
+                if pending && self.ime_next == Some(true) {
+                    // EI right before HALT, the interrupt returns to the HALT
+                    self.reg.pc = self.reg.pc.wrapping_sub(1);
+                } else if pending && !self.ime {
+                    self.halt_bug = true;
+                } else {
+                    self.is_halted = true;
+                }
             }
             // RST n
             0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
@@ -1523,18 +1644,24 @@ impl Cpu {
             0xD9 => {
                 let d16 = self.stack_pop();
                 self.reg.pc = d16;
-                self.ime_next = Some(true);
+                self.ime = true;
             }
-            // DI
+            // DI, unlike EI it takes effect right away
             0xF3 => {
-                self.ime_next = Some(false);
+                self.ime = false;
+                self.ime_next = None;
             }
             // EI
             0xFB => {
                 self.ime_next = Some(true);
             }
             0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
-                panic!("this opcode not exist {:02x}", opcode);
+                let lockup = Lockup {
+                    opcode,
+                    address: self.reg.pc.wrapping_sub(1),
+                };
+                self.lockup = Some(lockup);
+                self.new_lockup = Some(lockup);
             }
         };
 
@@ -1753,118 +1880,53 @@ impl Cpu {
         let r = n | (1 << bit);
         r
     }
+    // the high byte is written first, after an internal cycle
     fn stack_push(&mut self, value: u16) {
-        self.reg.sp = self.reg.sp - 2;
-        self.mmu.borrow_mut().set_word(self.reg.sp, value);
+        let (value_low, value_high) = u8u8_from_u16(value);
+        self.tick();
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.write(self.reg.sp, value_high);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.write(self.reg.sp, value_low);
     }
     fn stack_pop(&mut self) -> u16 {
-        let value = self.mmu.borrow_mut().get_word(self.reg.sp);
-        // self.mmu.borrow_mut().set_word(self.reg.sp, 0);
-        self.reg.sp = self.reg.sp + 2;
+        let value = self.read_word(self.reg.sp);
+        // self.write_word(self.reg.sp, 0);
+        self.reg.sp = self.reg.sp.wrapping_add(2);
         value
     }
-    fn imm(&mut self) -> u8 {
-        let v = self.mmu.borrow().get(self.reg.pc);
-        self.reg.pc += 1;
-        v
+    fn tick(&mut self) {
+        self.ticks += 1;
+        self.bus.tick();
     }
-    fn imm_word(&mut self) -> u16 {
-        let low = self.mmu.borrow().get(self.reg.pc);
-        let high = self.mmu.borrow().get(self.reg.pc + 1);
-        self.reg.pc += 2;
-        u16_from_2u8(low, high)
+    // every access takes a machine cycle
+    fn read(&mut self, index: u16) -> u8 {
+        self.tick();
+        self.bus.get(index)
     }
-}
-
-#[derive(serde::Deserialize, serde::Serialize)]
-pub enum TimerClock {
-    S0 = 1024,
-    S1 = 16,
-    S2 = 64,
-    S3 = 256,
-}
-#[derive(serde::Deserialize, serde::Serialize)]
-pub struct Timer {
-    mode: GameBoyMode,
-    div_cycles: usize,
-    tima_cycles: usize,
-    #[serde(skip)]
-    pub mmu: Rc<RefCell<Mmu>>,
-    cur_tac: TimerClock,
-}
-impl Timer {
-    pub fn new(mode: GameBoyMode, mmu: Rc<RefCell<Mmu>>) -> Self {
-        Self {
-            mode,
-            div_cycles: 0,
-            tima_cycles: 0,
-            mmu,
-            cur_tac: TimerClock::S3,
-        }
+    fn write(&mut self, index: u16, value: u8) {
+        self.tick();
+        self.bus.set(index, value);
     }
-    pub fn trick(&mut self) {
-        self.trick_div();
-        self.trick_tima();
-        if self.mode == GameBoyMode::GBC && self.mmu.borrow().speed.current_speed {
-            self.trick_div();
-            self.trick_tima();
-        }
+    fn read_word(&mut self, index: u16) -> u16 {
+        let low = self.read(index);
+        let high = self.read(index.wrapping_add(1));
+        u16_from_2u8(low, high)
     }
-    fn trick_div(&mut self) {
-        let time_clock = TimerClock::S3 as usize;
-        if self.div_cycles == 0 {
-            let mut div = self.mmu.borrow().get(0xFF04);
-            div = div.wrapping_add(1);
-            self.mmu.borrow_mut().set(0xFF04, div)
-        }
-        if self.div_cycles >= time_clock - 1 {
-            self.div_cycles = 0;
-        } else {
-            self.div_cycles += 1;
-        }
+    fn write_word(&mut self, index: u16, value: u16) {
+        let (value_low, value_high) = u8u8_from_u16(value);
+        self.write(index, value_low);
+        self.write(index.wrapping_add(1), value_high);
     }
-    fn trick_tima(&mut self) {
-        let tac = self.mmu.borrow().get(0xFF07);
-        let time_enable = check_bit(tac, 2);
-        if !time_enable {
-            self.tima_cycles = 0;
-            return;
-        }
-        let time_clock: usize = match self.cur_tac {
-            TimerClock::S0 => 1024,
-            TimerClock::S1 => 16,
-            TimerClock::S2 => 64,
-            TimerClock::S3 => 256,
-        };
-        if self.tima_cycles == 0 {
-            let input_clock_select = match tac & 0x3 {
-                0b00 => TimerClock::S0,
-                0b01 => TimerClock::S1,
-                0b10 => TimerClock::S2,
-                0b11 => TimerClock::S3,
-                _ => panic!("input_clock_select error"),
-            };
-            self.cur_tac = input_clock_select;
-            let tma = self.mmu.borrow().get(0xFF06);
-            let tima = self.mmu.borrow().get(0xFF05);
-            match tima.checked_add(1) {
-                Some(tima) => {
-                    self.mmu.borrow_mut().set(0xFF05, tima);
-                }
-                None => {
-                    self.mmu.borrow_mut().set(0xFF05, tma);
-                    self.set_timer_interrupt();
-                }
-            };
-        }
-        if self.tima_cycles >= time_clock - 1 {
-            self.tima_cycles = 0;
-        } else {
-            self.tima_cycles += 1;
-        }
+    fn imm(&mut self) -> u8 {
+        let v = self.read(self.reg.pc);
+        self.reg.pc = self.reg.pc.wrapping_add(1);
+        v
     }
-    fn set_timer_interrupt(&mut self) {
-        let d8 = self.mmu.borrow_mut().get(0xFF0F);
-        self.mmu.borrow_mut().set(0xFF0F, d8 | 0b100);
+    fn imm_word(&mut self) -> u16 {
+        let low = self.read(self.reg.pc);
+        let high = self.read(self.reg.pc.wrapping_add(1));
+        self.reg.pc = self.reg.pc.wrapping_add(2);
+        u16_from_2u8(low, high)
     }
 }
@@ -0,0 +1,243 @@
+use crate::gameboy::{GameBoy, Lockup, FRAME_CYCLES, FRAME_TIMEOUT};
+use std::cell::{Cell, RefCell};
+
+// a second of emulation, for steps that never finish
+const STEP_CYCLES: usize = 60 * FRAME_CYCLES;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    // the opcode fetch of an instruction, operands are reads
+    Fetch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+    Execute,
+}
+impl WatchKind {
+    fn matches(&self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::ReadWrite => access != Access::Fetch,
+            WatchKind::Execute => access == Access::Fetch,
+        }
+    }
+}
+
+// bank None matches every bank
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub bank: Option<usize>,
+    pub address: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub address: u16,
+    pub kind: WatchKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(Breakpoint),
+    // an instruction runs all at once, so the one at pc that made the access has already been
+    // executed when the run stops
+    Watchpoint {
+        pc: u16,
+        address: u16,
+        value: u8,
+        access: Access,
+    },
+    // a refresh, or a frame worth of cycles while the LCD is off
+    Frame,
+    Lockup(Lockup),
+    // the cycles given to the run are used up
+    Timeout,
+}
+
+// memory accesses made by the cpu, recorded by the bus while enabled
+#[derive(Default)]
+pub struct AccessLog {
+    enable: Cell<bool>,
+    accesses: RefCell<Vec<(u16, u8, Access)>>,
+}
+impl AccessLog {
+    pub fn set_enable(&self, enable: bool) {
+        self.enable.set(enable);
+    }
+    pub fn push(&self, address: u16, value: u8, access: Access) {
+        if self.enable.get() {
+            self.accesses.borrow_mut().push((address, value, access));
+        }
+    }
+    pub fn take(&self) -> Vec<(u16, u8, Access)> {
+        self.accesses.take()
+    }
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    break_on_lockup: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.retain(|b| *b != breakpoint);
+    }
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.retain(|w| *w != watchpoint);
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+    // stop when the cpu locks up, the lockup isn't reported to the host then
+    pub fn set_break_on_lockup(&mut self, break_on_lockup: bool) {
+        self.break_on_lockup = break_on_lockup;
+    }
+    // run one instruction, while halted one step of the halted cpu
+    pub fn step(&self, gameboy: &mut GameBoy) -> StopReason {
+        self.run(gameboy, |_| true, false, STEP_CYCLES)
+    }
+    // like step, but runs CALL and RST until they return
+    pub fn step_over(&self, gameboy: &mut GameBoy) -> StopReason {
+        let registers = gameboy.registers();
+        let length = match gameboy.peek(registers.pc) {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => 1,
+            _ => return self.step(gameboy),
+        };
+        let target = registers.pc.wrapping_add(length);
+        let sp = registers.sp;
+        self.run(
+            gameboy,
+            |gameboy| {
+                let registers = gameboy.registers();
+                registers.pc == target && registers.sp >= sp
+            },
+            false,
+            STEP_CYCLES,
+        )
+    }
+    // run until a return leaves the current function
+    pub fn step_out(&self, gameboy: &mut GameBoy) -> StopReason {
+        let registers = gameboy.registers();
+        let sp = registers.sp;
+        let mut last_opcode = gameboy.peek(registers.pc);
+        self.run(
+            gameboy,
+            |gameboy| {
+                let registers = gameboy.registers();
+                let is_return = matches!(last_opcode, 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9);
+                last_opcode = gameboy.peek(registers.pc);
+                is_return && registers.sp > sp
+            },
+            false,
+            STEP_CYCLES,
+        )
+    }
+    // run until the next refresh, or one frame worth of cycles while the LCD is off, the same
+    // frame as GameBoy::run_frame
+    pub fn run_to_frame(&self, gameboy: &mut GameBoy) -> StopReason {
+        self.run(gameboy, |_| false, true, FRAME_TIMEOUT)
+    }
+    fn run(
+        &self,
+        gameboy: &mut GameBoy,
+        mut done: impl FnMut(&GameBoy) -> bool,
+        stop_at_frame: bool,
+        cycles: usize,
+    ) -> StopReason {
+        gameboy.set_log_cpu_access(true);
+        // don't stop again at the instruction we are standing on
+        let mut skip = gameboy.is_instruction_boundary();
+        let mut pc = gameboy.registers().pc;
+        let mut cycle = 0;
+        let reason = loop {
+            if cycle == cycles {
+                break StopReason::Timeout;
+            }
+            cycle += 1;
+            if gameboy.is_instruction_boundary() {
+                pc = gameboy.registers().pc;
+                if skip {
+                    skip = false;
+                } else {
+                    if let Some(breakpoint) = self.check_breakpoint(gameboy) {
+                        break StopReason::Breakpoint(breakpoint);
+                    }
+                    if done(gameboy) {
+                        break StopReason::Step;
+                    }
+                }
+            }
+            let is_refresh = gameboy.trick();
+            if let Some(reason) = self.check_watchpoint(gameboy, pc) {
+                break reason;
+            }
+            if self.break_on_lockup {
+                if let Some(lockup) = gameboy.take_lockup() {
+                    break StopReason::Lockup(lockup);
+                }
+            }
+            if stop_at_frame && gameboy.is_frame_done(is_refresh, cycle) {
+                break StopReason::Frame;
+            }
+        };
+        gameboy.set_log_cpu_access(false);
+        reason
+    }
+    fn check_breakpoint(&self, gameboy: &GameBoy) -> Option<Breakpoint> {
+        if self.breakpoints.is_empty() || gameboy.is_halted() {
+            return None;
+        }
+        let pc = gameboy.registers().pc;
+        let bank = gameboy.mmu().bank(pc);
+        self.breakpoints
+            .iter()
+            .find(|b| b.address == pc && (b.bank.is_none() || b.bank == bank))
+            .copied()
+    }
+    fn check_watchpoint(&self, gameboy: &GameBoy, pc: u16) -> Option<StopReason> {
+        let accesses = gameboy.mmu().access_log.take();
+        for (address, value, access) in accesses {
+            let hit = self
+                .watchpoints
+                .iter()
+                .any(|w| w.address == address && w.kind.matches(access));
+            if hit {
+                return Some(StopReason::Watchpoint {
+                    pc,
+                    address,
+                    value,
+                    access,
+                });
+            }
+        }
+        None
+    }
+}
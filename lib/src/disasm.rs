@@ -0,0 +1,238 @@
+use crate::cpu::{CB_CYCLES, OP_CYCLES};
+use crate::util::u16_from_2u8;
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP ",
+];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    // machine cycles, the branch not taken for conditional instructions
+    pub cycles: u32,
+    // destination of jumps, calls and restarts
+    pub target: Option<u16>,
+    // the input ended inside the instruction, bytes only has what there was
+    pub truncated: bool,
+}
+
+impl Instruction {
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+}
+
+// the signed offset of ADD SP and LD HL,SP, as +$05 or -$03
+fn signed_hex(value: u8) -> String {
+    let value = value as i8;
+    let sign = if value < 0 { '-' } else { '+' };
+    format!("{}${:02X}", sign, value.unsigned_abs())
+}
+
+// bytes start at the instruction, missing operands read as 0x00 in the mnemonic
+pub fn disassemble(address: u16, bytes: &[u8]) -> Instruction {
+    let byte = |index: usize| bytes.get(index).copied().unwrap_or(0);
+    let opcode = byte(0);
+    let d8 = byte(1);
+    let d16 = u16_from_2u8(byte(1), byte(2));
+    let relative = address.wrapping_add(2).wrapping_add(d8 as i8 as u16);
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 0x07) as usize;
+    let z = (opcode & 0x07) as usize;
+    let p = y >> 1;
+    let q = y & 0x01;
+
+    let mut length = 1;
+    let mut target = None;
+    let mut cycles = OP_CYCLES[opcode as usize];
+    let mnemonic = match x {
+        0 => match z {
+            0 => match y {
+                0 => "NOP".to_owned(),
+                1 => {
+                    length = 3;
+                    format!("LD (${:04X}),SP", d16)
+                }
+                2 => {
+                    length = 2;
+                    "STOP".to_owned()
+                }
+                3 => {
+                    length = 2;
+                    target = Some(relative);
+                    format!("JR ${:04X}", relative)
+                }
+                _ => {
+                    length = 2;
+                    target = Some(relative);
+                    format!("JR {},${:04X}", CC[y - 4], relative)
+                }
+            },
+            1 => {
+                if q == 0 {
+                    length = 3;
+                    format!("LD {},${:04X}", RP[p], d16)
+                } else {
+                    format!("ADD HL,{}", RP[p])
+                }
+            }
+            2 => {
+                let memory = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+                if q == 0 {
+                    format!("LD {},A", memory)
+                } else {
+                    format!("LD A,{}", memory)
+                }
+            }
+            3 => {
+                if q == 0 {
+                    format!("INC {}", RP[p])
+                } else {
+                    format!("DEC {}", RP[p])
+                }
+            }
+            4 => format!("INC {}", R[y]),
+            5 => format!("DEC {}", R[y]),
+            6 => {
+                length = 2;
+                format!("LD {},${:02X}", R[y], d8)
+            }
+            _ => ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"][y].to_owned(),
+        },
+        1 => {
+            if y == 6 && z == 6 {
+                "HALT".to_owned()
+            } else {
+                format!("LD {},{}", R[y], R[z])
+            }
+        }
+        2 => format!("{}{}", ALU[y], R[z]),
+        _ => match z {
+            0 => match y {
+                0..=3 => format!("RET {}", CC[y]),
+                4 => {
+                    length = 2;
+                    format!("LDH ($FF{:02X}),A", d8)
+                }
+                5 => {
+                    length = 2;
+                    format!("ADD SP,{}", signed_hex(d8))
+                }
+                6 => {
+                    length = 2;
+                    format!("LDH A,($FF{:02X})", d8)
+                }
+                _ => {
+                    length = 2;
+                    format!("LD HL,SP{}", signed_hex(d8))
+                }
+            },
+            1 => {
+                if q == 0 {
+                    format!("POP {}", RP2[p])
+                } else {
+                    ["RET", "RETI", "JP HL", "LD SP,HL"][p].to_owned()
+                }
+            }
+            2 => match y {
+                0..=3 => {
+                    length = 3;
+                    target = Some(d16);
+                    format!("JP {},${:04X}", CC[y], d16)
+                }
+                4 => "LD ($FF00+C),A".to_owned(),
+                5 => {
+                    length = 3;
+                    format!("LD (${:04X}),A", d16)
+                }
+                6 => "LD A,($FF00+C)".to_owned(),
+                _ => {
+                    length = 3;
+                    format!("LD A,(${:04X})", d16)
+                }
+            },
+            3 => match y {
+                0 => {
+                    length = 3;
+                    target = Some(d16);
+                    format!("JP ${:04X}", d16)
+                }
+                1 => {
+                    length = 2;
+                    cycles = CB_CYCLES[d8 as usize];
+                    disassemble_cb(d8)
+                }
+                6 => "DI".to_owned(),
+                7 => "EI".to_owned(),
+                _ => format!("DB ${:02X}", opcode),
+            },
+            4 => {
+                if y < 4 {
+                    length = 3;
+                    target = Some(d16);
+                    format!("CALL {},${:04X}", CC[y], d16)
+                } else {
+                    format!("DB ${:02X}", opcode)
+                }
+            }
+            5 => {
+                if q == 0 {
+                    format!("PUSH {}", RP2[p])
+                } else if p == 0 {
+                    length = 3;
+                    target = Some(d16);
+                    format!("CALL ${:04X}", d16)
+                } else {
+                    format!("DB ${:02X}", opcode)
+                }
+            }
+            6 => {
+                length = 2;
+                format!("{}${:02X}", ALU[y], d8)
+            }
+            _ => {
+                let vector = (y * 8) as u16;
+                target = Some(vector);
+                format!("RST ${:02X}", vector)
+            }
+        },
+    };
+    Instruction {
+        address,
+        bytes: bytes.iter().take(length).copied().collect(),
+        mnemonic,
+        cycles,
+        target,
+        truncated: bytes.len() < length,
+    }
+}
+
+fn disassemble_cb(opcode: u8) -> String {
+    let y = ((opcode >> 3) & 0x07) as usize;
+    let z = (opcode & 0x07) as usize;
+    match opcode >> 6 {
+        0 => format!("{} {}", ROT[y], R[z]),
+        1 => format!("BIT {},{}", y, R[z]),
+        2 => format!("RES {},{}", y, R[z]),
+        _ => format!("SET {},{}", y, R[z]),
+    }
+}
+
+// every instruction of a block of bytes mapped at address
+pub fn disassemble_block(address: u16, bytes: &[u8]) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = disassemble(address.wrapping_add(offset as u16), &bytes[offset..]);
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
@@ -1,13 +1,21 @@
+pub use crate::apu::ChannelState;
+use crate::bus::Bus;
 use crate::cartridge::{from_vecu8, Cartridge, Stable};
-use crate::cpu::{Cpu, Timer};
+use crate::compat;
+use crate::cpu::Cpu;
+pub use crate::cpu::{CpuState, Lockup, Registers};
+use crate::disasm::{disassemble, Instruction};
 use crate::gameboy_mode::GameBoyMode;
+pub use crate::gameboy_mode::Model;
+pub use crate::hooks::{HookEvent, HookId, HookKind, Hooks};
 use crate::joypad::JoyPadKey;
+use crate::memory::Memory;
 use crate::mmu::{CartridgeProxy, Mmu};
 use crate::ppu::PPU;
-pub use crate::ppu::{HEIGHT, WIDTH};
+pub use crate::ppu::{DmgPalette, HEIGHT, WIDTH};
 use bincode::Error;
-use std::ops::Deref;
-use std::{cell::RefCell, rc::Rc};
+use std::io::Write;
+use std::ops::RangeInclusive;
 /*
 use std::fs::File;
 use simplelog::*;
@@ -15,6 +23,11 @@ extern crate log;
 extern crate simplelog;
 */
 
+pub const FRAME_CYCLES: usize = 70224;
+// the refresh can come a few cycles past FRAME_CYCLES when an instruction ends after it, and
+// up to a whole frame later when the LCD has just been turned on
+pub(crate) const FRAME_TIMEOUT: usize = FRAME_CYCLES * 2;
+
 #[derive(Default, serde::Deserialize, serde::Serialize)]
 pub struct GameBoyStatus {
     other_status: Vec<u8>,
@@ -23,14 +36,84 @@ pub struct GameBoyStatus {
     cartridge_status: Vec<u8>,
 }
 
+// the bus of the whole machine, the timer, apu and ppu run along with every machine cycle of the cpu
 #[derive(serde::Deserialize, serde::Serialize)]
-pub struct GameBoy {
+struct Board {
     #[serde(skip)]
-    pub mmu: Rc<RefCell<Mmu>>,
+    mmu: Box<Mmu>,
     #[serde(skip)]
     ppu: PPU,
-    cpu: Cpu,
-    timer: Timer,
+    #[serde(skip)]
+    refresh: bool,
+}
+impl Board {
+    fn trick(&mut self) {
+        self.mmu.apu.trick();
+        let oam_dma = self.mmu.oam_dma.active();
+        if self.ppu.trick(&mut self.mmu.ppu, oam_dma) {
+            self.refresh = true;
+        }
+    }
+}
+impl Memory for Board {
+    fn get(&self, index: u16) -> u8 {
+        self.mmu.get(index)
+    }
+    fn set(&mut self, index: u16, value: u8) {
+        self.mmu.set(index, value);
+    }
+}
+impl Bus for Board {
+    // a machine cycle is 4 clocks, 2 in double speed
+    fn tick(&mut self) {
+        self.mmu.tick();
+        let clocks = if self.double_speed() { 2 } else { 4 };
+        for _ in 0..clocks {
+            self.trick();
+        }
+    }
+    fn fetch(&self, index: u16) -> u8 {
+        self.mmu.fetch(index)
+    }
+    fn peek(&self, index: u16) -> u8 {
+        self.mmu.peek(index)
+    }
+    fn poke(&mut self, index: u16, value: u8) {
+        self.mmu.poke(index, value);
+    }
+    fn double_speed(&self) -> bool {
+        self.mmu.double_speed()
+    }
+    fn set_double_speed(&mut self, double_speed: bool) {
+        self.mmu.set_double_speed(double_speed);
+    }
+    fn switch_speed(&mut self) -> bool {
+        self.mmu.switch_speed()
+    }
+    fn reset_div(&mut self) {
+        self.mmu.reset_div();
+    }
+    fn stall(&mut self) {
+        self.mmu.stall();
+        let clocks = if self.double_speed() { 2 } else { 4 };
+        for _ in 0..clocks {
+            self.trick();
+        }
+    }
+    fn joypad_low(&self) -> bool {
+        self.mmu.joypad_low()
+    }
+    fn dma_stall(&mut self) -> u32 {
+        self.mmu.dma_stall()
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct GameBoy {
+    model: Model,
+    cpu: Cpu<Board>,
+    #[serde(skip)]
+    log_cpu_access: bool,
 }
 
 impl GameBoy {
@@ -51,39 +134,203 @@ impl GameBoy {
           ])
           .unwrap();
           */
-        let skip_bios = bios.is_empty();
-        let gbc_flag = cartridge.gbc_flag();
-        let mode = if gbc_flag {
-            GameBoyMode::GBC
+        // a GBC boot rom takes GB cartridges too
+        let model = if cartridge.gbc_flag() || bios.len() > 0x100 {
+            Model::CGB
         } else {
-            GameBoyMode::GB
+            Model::DMG
+        };
+        Self::with_model(model, bios, cartridge)
+    }
+    pub fn with_model(model: Model, bios: Vec<u8>, cartridge: Box<dyn Cartridge>) -> Self {
+        let skip_bios = bios.is_empty();
+        let mode = model.mode(cartridge.gbc_flag(), skip_bios);
+        let mut mmu = Mmu::new(mode, bios, cartridge);
+        if skip_bios {
+            mmu.skip_boot(model);
+        }
+        let board = Board {
+            ppu: PPU::new(mmu.mode),
+            mmu: Box::new(mmu),
+            refresh: false,
         };
-        let mmu = Mmu::new(mode, bios, cartridge);
-        let rc_refcell_mmu = Rc::new(RefCell::new(mmu));
-        let cpu = Cpu::new(rc_refcell_mmu.clone(), skip_bios);
-        let ppu = PPU::new(rc_refcell_mmu.clone());
-        let timer = Timer::new(mode, rc_refcell_mmu.clone());
+        let cpu = Cpu::new(model, board, skip_bios);
         Self {
-            mmu: rc_refcell_mmu.clone(),
+            model,
             cpu,
-            ppu,
-            timer,
+            log_cpu_access: false,
         }
     }
+    // the cpu runs a whole step on its first clock and the rest of the machine with it,
+    // the following clocks of the step only wait
     pub fn trick(&mut self) -> bool {
-        self.cpu.trick();
-        self.timer.trick();
-        let is_refresh = self.ppu.trick();
+        if self.log_cpu_access {
+            self.mmu().access_log.set_enable(true);
+            self.cpu.trick();
+            self.mmu().access_log.set_enable(false);
+        } else {
+            self.cpu.trick();
+        }
+        let is_refresh = self.cpu.bus.refresh;
+        self.cpu.bus.refresh = false;
         is_refresh
     }
+    // run until the next refresh, or one frame worth of cycles while the LCD is off
+    pub fn run_frame(&mut self) {
+        for cycles in 1..=FRAME_TIMEOUT {
+            let refresh = self.trick();
+            if self.is_frame_done(refresh, cycles) {
+                return;
+            }
+        }
+    }
+    // a refresh, or a frame worth of cycles run while the LCD is off, which has none
+    pub(crate) fn is_frame_done(&self, refresh: bool, cycles: usize) -> bool {
+        refresh || cycles >= FRAME_CYCLES && self.peek(0xFF40) & 0x80 == 0
+    }
+    pub fn mmu(&self) -> &Mmu {
+        &self.cpu.bus.mmu
+    }
+    pub fn mmu_mut(&mut self) -> &mut Mmu {
+        &mut self.cpu.bus.mmu
+    }
+    pub fn model(&self) -> Model {
+        self.model
+    }
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state()
+    }
+    pub fn set_cpu_state(&mut self, state: CpuState) {
+        self.cpu.set_state(state);
+    }
+    // memory as the cpu would see it, without logging the access or running hooks
+    pub fn peek(&self, index: u16) -> u8 {
+        self.mmu().read(index)
+    }
+    // see Mmu::store for what the I/O registers keep
+    pub fn poke(&mut self, index: u16, value: u8) {
+        self.mmu_mut().store(index, value);
+    }
+    pub fn peek_rom(&self, bank: usize, index: u16) -> u8 {
+        self.mmu().peek_rom(bank, index)
+    }
+    pub fn peek_vram(&self, bank: usize, index: u16) -> u8 {
+        self.mmu().peek_vram(bank, index)
+    }
+    pub fn peek_wram(&self, bank: usize, index: u16) -> u8 {
+        self.mmu().peek_wram(bank, index)
+    }
+    pub fn peek_sram(&self, bank: usize, index: u16) -> u8 {
+        self.mmu().peek_sram(bank, index)
+    }
+    pub fn is_halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+    // the cpu ran an illegal opcode, frames are still rendered
+    pub fn lockup(&self) -> Option<Lockup> {
+        self.cpu.lockup()
+    }
+    // the lockup the first time it's asked for, None afterwards
+    pub fn take_lockup(&mut self) -> Option<Lockup> {
+        self.cpu.take_lockup()
+    }
+    // log every instruction in the gameboy-doctor format, None stops logging
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.cpu.set_trace(trace);
+    }
+    pub fn disassemble(&self, index: u16) -> Instruction {
+        let bytes: Vec<u8> = (0..3)
+            .map(|offset| self.peek(index.wrapping_add(offset)))
+            .collect();
+        disassemble(index, &bytes)
+    }
+    pub(crate) fn is_instruction_boundary(&self) -> bool {
+        self.cpu.is_instruction_boundary()
+    }
+    pub(crate) fn set_log_cpu_access(&mut self, log_cpu_access: bool) {
+        self.log_cpu_access = log_cpu_access;
+        self.mmu().access_log.take();
+    }
     pub fn flip(&mut self) -> bool {
         self.cpu.flip()
     }
     pub fn get_frame_buffer(&self) -> &[u32; WIDTH * HEIGHT] {
-        &self.ppu.frame_buffer
+        &self.cpu.bus.ppu.frame_buffer
+    }
+    // colors of DMG games, GBC games bring their own
+    pub fn dmg_palette(&self) -> DmgPalette {
+        self.cpu.bus.ppu.palette()
+    }
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.cpu.bus.ppu.set_palette(palette);
+    }
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mmu_mut().apu.set_sample_rate(sample_rate);
+    }
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.mmu_mut().apu.take_samples()
+    }
+    pub fn take_audio_samples_i16(&mut self) -> Vec<i16> {
+        self.take_audio_samples()
+            .iter()
+            .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect()
+    }
+    // channel 0~3 are square 1, square 2, wave and noise
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.mmu_mut().apu.set_channel_muted(channel, muted);
+    }
+    pub fn set_channel_solo(&mut self, channel: usize, solo: bool) {
+        self.mmu_mut().apu.set_channel_solo(channel, solo);
+    }
+    pub fn channel_state(&self, channel: usize) -> Option<ChannelState> {
+        self.mmu().apu.channel_state(channel)
+    }
+    pub fn set_capture_channels(&mut self, capture: bool) {
+        self.mmu_mut().apu.set_capture_channels(capture);
+    }
+    pub fn take_channel_samples(&mut self, channel: usize) -> Vec<f32> {
+        self.mmu_mut().apu.take_channel_samples(channel)
+    }
+    // callbacks on the memory accesses of the cpu
+    pub fn add_hook(
+        &mut self,
+        kind: HookKind,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(&HookEvent) + 'static,
+    ) -> HookId {
+        self.mmu_mut().hooks.add(kind, range, callback)
+    }
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.mmu_mut().hooks.remove(id)
+    }
+    // hooks aren't part of a save state, these carry them over to a loaded gameboy
+    pub fn take_hooks(&mut self) -> Hooks {
+        std::mem::take(&mut self.mmu_mut().hooks)
+    }
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.mmu_mut().hooks = hooks;
+    }
+    // the colors the GBC boot rom gives a GB game for keys held during its logo, for when it's
+    // skipped, false if the keys don't pick any or the game isn't a GB game on a GBC
+    pub fn select_compat_palette(&mut self, keys: &[JoyPadKey]) -> bool {
+        if self.mmu().mode != GameBoyMode::GBCCompat {
+            return false;
+        }
+        let keys = keys.iter().fold(0, |bits, key| bits | key.clone() as u8);
+        match compat::key_palette_id(keys) {
+            Some(id) => {
+                self.mmu_mut().set_compat_palettes(id);
+                true
+            }
+            None => false,
+        }
     }
     pub fn input(&mut self, key: JoyPadKey, is_pressed: bool) {
-        self.mmu.borrow_mut().joypad.input(key, is_pressed);
+        self.mmu_mut().joypad.input(key, is_pressed);
     }
     pub fn is_gbc(cartridge: Box<dyn Cartridge>) -> bool {
         cartridge.gbc_flag()
@@ -97,15 +344,12 @@ impl GameBoy {
         let mut gameboy: Self = bincode::deserialize_from(status.other_status.as_slice())?;
         let mut mmu: Mmu = bincode::deserialize_from(status.mmu_status.as_slice())?;
         mmu.cartridge = CartridgeProxy { content: cartridge };
-        let rc_refcell_mmu = Rc::new(RefCell::new(mmu));
-        gameboy.mmu = rc_refcell_mmu.clone();
-        gameboy.cpu.mmu = rc_refcell_mmu.clone();
-        gameboy.ppu = PPU::new(rc_refcell_mmu.clone());
-        gameboy.timer.mmu = rc_refcell_mmu.clone();
+        gameboy.cpu.bus.ppu = PPU::new(mmu.mode);
+        gameboy.cpu.bus.mmu = Box::new(mmu);
+        gameboy.set_dmg_palette(self.dmg_palette());
         gameboy.load_sav(status.ram.clone());
         gameboy
-            .mmu
-            .borrow_mut()
+            .mmu_mut()
             .cartridge
             .content
             .load_status(status.cartridge_status.clone());
@@ -116,9 +360,9 @@ impl GameBoy {
         let mut other_data = Vec::new();
         bincode::serialize_into(&mut other_data, &self)?;
         let mut mmu_data = Vec::new();
-        bincode::serialize_into(&mut mmu_data, self.mmu.borrow().deref())?;
+        bincode::serialize_into(&mut mmu_data, self.mmu())?;
         let ram = self.save_sav();
-        let cartridge_status = self.mmu.borrow().cartridge.content.save_status();
+        let cartridge_status = self.mmu().cartridge.content.save_status();
         let status = GameBoyStatus {
             other_status: other_data,
             mmu_status: mmu_data,
@@ -133,9 +377,9 @@ impl GameBoy {
 
 impl Stable for GameBoy {
     fn save_sav(&self) -> Vec<u8> {
-        self.mmu.borrow().save_sav()
+        self.mmu().save_sav()
     }
     fn load_sav(&mut self, ram: Vec<u8>) {
-        self.mmu.borrow_mut().load_sav(ram);
+        self.mmu_mut().load_sav(ram);
     }
 }
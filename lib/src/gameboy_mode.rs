@@ -3,4 +3,49 @@
 pub enum GameBoyMode {
     GB,
     GBC,
+    // a GBC running GB software, the boot rom picks the colors and locks the GBC features
+    GBCCompat,
+}
+
+// the hardware being emulated, software tells them apart by the registers the boot rom leaves
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub enum Model {
+    DMG0,
+    DMG,
+    MGB,
+    SGB,
+    SGB2,
+    CGB,
+    AGB,
+}
+
+impl Model {
+    pub fn is_gbc(self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+    // a GBC boot rom switches to GBCCompat by itself when it runs
+    pub fn mode(self, gbc_flag: bool, skip_boot: bool) -> GameBoyMode {
+        match (self.is_gbc(), gbc_flag, skip_boot) {
+            (false, _, _) => GameBoyMode::GB,
+            (true, false, true) => GameBoyMode::GBCCompat,
+            (true, _, _) => GameBoyMode::GBC,
+        }
+    }
+}
+
+impl std::str::FromStr for Model {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dmg0" => Ok(Model::DMG0),
+            "dmg" => Ok(Model::DMG),
+            "mgb" => Ok(Model::MGB),
+            "sgb" => Ok(Model::SGB),
+            "sgb2" => Ok(Model::SGB2),
+            "cgb" => Ok(Model::CGB),
+            "agb" => Ok(Model::AGB),
+            _ => Err(format!("unknown model: {}", s)),
+        }
+    }
 }
@@ -0,0 +1,114 @@
+use crate::memory::Memory;
+
+// machine cycles a block of 16 bytes holds the cpu for, twice as many in double speed
+pub const BLOCK_CYCLES: u32 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum HDMAMode {
+    GeneralPurposeDMA,
+    HBlankDMA,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct HDMA {
+    pub mode: HDMAMode,
+    pub source: u16,
+    // offset into VRAM
+    pub destination: u16,
+    pub active: bool,
+    // blocks left, FF55 keeps showing them after a cancel
+    pub remain: u8,
+    // set once the current hblank got its block
+    hblank: bool,
+}
+
+impl HDMA {
+    pub fn new() -> Self {
+        Self {
+            mode: HDMAMode::GeneralPurposeDMA,
+            source: 0xFFF0,
+            destination: 0x1FF0,
+            active: false,
+            remain: 0,
+            hblank: false,
+        }
+    }
+    // blocks to copy now, hblank is false while the ppu is busy with a line. a general purpose
+    // transfer gives one block at a time, FF55 counts down while it holds the cpu
+    pub fn blocks_due(&mut self, hblank: bool) -> u8 {
+        if !self.active {
+            return 0;
+        }
+        match self.mode {
+            HDMAMode::GeneralPurposeDMA => 1,
+            HDMAMode::HBlankDMA => {
+                if !hblank {
+                    self.hblank = false;
+                    0
+                } else if self.hblank {
+                    0
+                } else {
+                    self.hblank = true;
+                    1
+                }
+            }
+        }
+    }
+    // source and VRAM address of the next block
+    pub fn take_block(&mut self) -> (u16, u16) {
+        let block = (self.source, 0x8000 | self.destination);
+        self.source = self.source.wrapping_add(0x10);
+        self.destination += 0x10;
+        self.remain -= 1;
+        // the transfer ends at the end of VRAM
+        if self.destination == 0x2000 {
+            self.destination = 0;
+            self.remain = 0;
+        }
+        if self.remain == 0 {
+            self.active = false;
+        }
+        block
+    }
+}
+
+impl Default for HDMA {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory for HDMA {
+    fn get(&self, index: u16) -> u8 {
+        match index {
+            0xFF51..=0xFF54 => 0xFF,
+            // bit 7 is clear while a transfer is running
+            0xFF55 => (!self.active as u8) << 7 | (self.remain.wrapping_sub(1) & 0x7F),
+            _ => panic!("HDMA get index not in 0xFF51~0xFF55"),
+        }
+    }
+    fn set(&mut self, index: u16, value: u8) {
+        match index {
+            0xFF51 => self.source = self.source & 0x00FF | (value as u16) << 8,
+            0xFF52 => self.source = self.source & 0xFF00 | (value & 0xF0) as u16,
+            0xFF53 => self.destination = self.destination & 0x00FF | ((value & 0x1F) as u16) << 8,
+            0xFF54 => self.destination = self.destination & 0x1F00 | (value & 0xF0) as u16,
+            0xFF55 => {
+                if self.active && self.mode == HDMAMode::HBlankDMA && value & 0x80 == 0 {
+                    self.active = false;
+                } else {
+                    self.mode = if value & 0x80 == 0 {
+                        HDMAMode::GeneralPurposeDMA
+                    } else {
+                        HDMAMode::HBlankDMA
+                    };
+                    self.remain = (value & 0x7F) + 1;
+                    self.active = true;
+                    // started in hblank or with the lcd off, the first block goes right away
+                    self.hblank = false;
+                }
+            }
+            _ => panic!("HDMA set index not in 0xFF51~0xFF55"),
+        }
+    }
+}
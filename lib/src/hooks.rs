@@ -0,0 +1,81 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookKind {
+    Read,
+    Write,
+    // the opcode fetch of an instruction, operands are reads
+    Execute,
+}
+
+// bank is the one mapped at address, as Mmu::bank gives it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HookEvent {
+    pub kind: HookKind,
+    pub address: u16,
+    pub value: u8,
+    pub bank: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HookId(usize);
+
+struct Hook {
+    id: HookId,
+    kind: HookKind,
+    range: RangeInclusive<u16>,
+    callback: Box<dyn FnMut(&HookEvent)>,
+}
+
+// Callbacks on the memory accesses of the cpu. They run while the mmu is borrowed, so they
+// can't reach back into the gameboy and have to keep what they need in their own state.
+#[derive(Default)]
+pub struct Hooks {
+    next_id: usize,
+    hooks: RefCell<Vec<Hook>>,
+}
+
+impl Hooks {
+    pub fn add(
+        &mut self,
+        kind: HookKind,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(&HookEvent) + 'static,
+    ) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.get_mut().push(Hook {
+            id,
+            kind,
+            range,
+            callback: Box::new(callback),
+        });
+        id
+    }
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let hooks = self.hooks.get_mut();
+        let len = hooks.len();
+        hooks.retain(|hook| hook.id != id);
+        hooks.len() != len
+    }
+    pub fn clear(&mut self) {
+        self.hooks.get_mut().clear();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.hooks.borrow().is_empty()
+    }
+    pub(crate) fn wants(&self, kind: HookKind, address: u16) -> bool {
+        self.hooks
+            .borrow()
+            .iter()
+            .any(|hook| hook.kind == kind && hook.range.contains(&address))
+    }
+    pub(crate) fn call(&self, event: &HookEvent) {
+        for hook in self.hooks.borrow_mut().iter_mut() {
+            if hook.kind == event.kind && hook.range.contains(&event.address) {
+                (hook.callback)(event);
+            }
+        }
+    }
+}
@@ -11,6 +11,22 @@ pub enum JoyPadKey {
     Select = 0b0100_0000,
     Start = 0b1000_0000,
 }
+impl std::str::FromStr for JoyPadKey {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "right" => Ok(JoyPadKey::Right),
+            "left" => Ok(JoyPadKey::Left),
+            "up" => Ok(JoyPadKey::Up),
+            "down" => Ok(JoyPadKey::Down),
+            "a" => Ok(JoyPadKey::A),
+            "b" => Ok(JoyPadKey::B),
+            "select" => Ok(JoyPadKey::Select),
+            "start" => Ok(JoyPadKey::Start),
+            _ => Err(format!("unknown key: {}", s)),
+        }
+    }
+}
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct JoyPad {
     matrix: u8,
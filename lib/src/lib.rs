@@ -1,11 +1,20 @@
+pub mod apu;
+pub mod bus;
 pub mod cartridge;
+pub mod compat;
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod hdma;
+pub mod hooks;
 pub mod memory;
 pub mod mmu;
 pub mod ppu;
+pub mod timer;
 pub mod joypad;
 pub mod gameboy;
 pub mod util;
 pub mod gameboy_mode;
+pub mod wav;
 mod big_array;
 mod test;
@@ -1,9 +1,17 @@
+use crate::apu::Apu;
 use crate::big_array::BigArray;
+use crate::bus::Bus;
 use crate::cartridge::{Cartridge, RomOnly, Stable};
-use crate::gameboy_mode::GameBoyMode;
+use crate::compat;
+use crate::debugger::{Access, AccessLog};
+use crate::gameboy_mode::{GameBoyMode, Model};
+use crate::hdma::{BLOCK_CYCLES, HDMA};
+use crate::hooks::{HookEvent, HookKind, Hooks};
 use crate::joypad::JoyPad;
 use crate::memory::Memory;
 use crate::ppu::PpuMmu;
+use crate::timer::Timer;
+use crate::util::check_bit;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 struct MemoryBlock {
@@ -41,83 +49,42 @@ impl Memory for MemoryBlock {
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
-pub enum HDMAMode {
-    GeneralPurposeDMA,
-    HBlankDMA,
+pub struct OamDma {
+    // FF46 reads back the last written value
+    register: u8,
+    // a written transfer waits a setup cycle before it starts
+    pending: Option<u16>,
+    starting: Option<u16>,
+    source: u16,
+    // bytes copied so far, None when no transfer is running
+    copied: Option<u16>,
+    // the byte on the bus in the current cycle
+    byte: u8,
 }
-
-#[derive(serde::Deserialize, serde::Serialize)]
-pub struct HDMA {
-    pub mode: HDMAMode,
-    pub source: u16,
-    pub destination: u16,
-    pub active: bool,
-    pub remain: usize,
-}
-impl HDMA {
+impl OamDma {
     fn new() -> Self {
         Self {
-            mode: HDMAMode::GeneralPurposeDMA,
-            source: 0xFFFF,
-            destination: 0xFFFF,
-            active: false,
-            remain: 0,
+            register: 0xFF,
+            pending: None,
+            starting: None,
+            source: 0,
+            copied: None,
+            byte: 0xFF,
         }
     }
+    pub fn active(&self) -> bool {
+        self.copied.is_some()
+    }
 }
-impl Memory for HDMA {
+impl Memory for OamDma {
     fn get(&self, index: u16) -> u8 {
-        match index {
-            0xFF51 => 0xFF,
-            0xFF52 => 0xFF,
-            0xFF53 => 0xFF,
-            0xFF54 => 0xFF,
-            0xFF55 => {
-                let bit7 = self.active as u8;
-                let low7bit = ((self.remain >> 4) - 1) as u8;
-                bit7 << 7 | low7bit
-            }
-            _ => panic!("HDMA get index not in 0xFF51~0xFF55"),
-        }
+        assert_eq!(index, 0xFF46);
+        self.register
     }
     fn set(&mut self, index: u16, value: u8) {
-        match index {
-            0xFF51 => {
-                self.source = self.source & 0x00FF | u16::from_be_bytes([value, 0]);
-            }
-            0xFF52 => {
-                self.source = self.source & 0xFF00 | u16::from_be_bytes([0, value & 0xF0]);
-            }
-            0xFF53 => {
-                self.destination =
-                    self.destination & 0x00FF | (u16::from_be_bytes([value, 0]) & 0x1F00 | 0x8000);
-            }
-            0xFF54 => {
-                self.destination =
-                    self.destination & 0xFF00 | u16::from_be_bytes([0, value & 0xF0]);
-            }
-            0xFF55 => {
-                let bit7 = value >> 7;
-                if !self.active {
-                    match bit7 {
-                        0 => {
-                            self.mode = HDMAMode::GeneralPurposeDMA;
-                        }
-                        1 => {
-                            self.mode = HDMAMode::HBlankDMA;
-                        }
-                        _ => panic!("hdma bit7 is {}", bit7),
-                    }
-                    self.remain = ((value & 0x7F) as usize + 1) << 4;
-                    self.active = true;
-                } else {
-                    if bit7 == 0 {
-                        self.active = false;
-                    }
-                }
-            }
-            _ => {}
-        }
+        assert_eq!(index, 0xFF46);
+        self.register = value;
+        self.pending = Some((value as u16) << 8);
     }
 }
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -183,11 +150,13 @@ impl Speed {
             memory: 0xFF,
         }
     }
-    pub fn switch(&mut self) {
-        if self.prepare_switch {
-            self.current_speed = !self.current_speed;
-            self.prepare_switch = false;
+    pub fn switch(&mut self) -> bool {
+        if !self.prepare_switch {
+            return false;
         }
+        self.current_speed = !self.current_speed;
+        self.prepare_switch = false;
+        true
     }
 }
 impl Memory for Speed {
@@ -223,15 +192,21 @@ pub struct Mmu {
     pub cartridge: CartridgeProxy,
     pub joypad: JoyPad,
     pub ppu: PpuMmu,
+    pub apu: Apu,
     wram: WRAM,
     other: MemoryBlock,
     pub hdma: HDMA,
     pub speed: Speed,
+    pub timer: Timer,
+    pub oam_dma: OamDma,
 
-    timer_flag: bool,
     serial_flag: bool,
 
     pub log_msg: Vec<u8>,
+    #[serde(skip)]
+    pub access_log: AccessLog,
+    #[serde(skip)]
+    pub hooks: Hooks,
 }
 
 impl Mmu {
@@ -244,6 +219,12 @@ impl Mmu {
         }
         let joypad = JoyPad::new();
         let ppu = PpuMmu::new(mode);
+        // the apu of a GBC keeps its quirks in GBCCompat
+        let apu = if mode == GameBoyMode::GB {
+            Apu::new(GameBoyMode::GB)
+        } else {
+            Apu::new(GameBoyMode::GBC)
+        };
         let hdma = HDMA::new();
         let speed = Speed::new();
         let wram = WRAM::new();
@@ -255,50 +236,372 @@ impl Mmu {
             other,
             joypad,
             ppu,
+            apu,
             wram,
             hdma,
             speed,
-            timer_flag: false,
+            timer: Timer::new(),
+            oam_dma: OamDma::new(),
             serial_flag: false,
             log_msg: vec![],
+            access_log: AccessLog::default(),
+            hooks: Hooks::default(),
         };
         if skip_boot {
             mmu.set(0xFF50, 1);
         }
         mmu
     }
+    // the I/O registers, VRAM and DIV as the boot rom of model leaves them
+    pub(crate) fn skip_boot(&mut self, model: Model) {
+        let sgb = matches!(model, Model::SGB | Model::SGB2);
+        // the SGB and GBC boot roms let go of the joypad, only the SGB one stays silent
+        self.write(0xFF00, if sgb || model.is_gbc() { 0x30 } else { 0x00 });
+        self.write(0xFF26, 0x80);
+        let nr14 = if sgb { 0x3F } else { 0xBF };
+        // the boot sound has faded out by then, so channel 1 starts from a silent envelope
+        let apu = [
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0x08),
+            (0xFF13, 0xFF),
+            (0xFF14, nr14),
+            (0xFF12, 0xF3),
+            (0xFF16, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF18, 0xFF),
+            (0xFF19, 0xBF),
+            (0xFF1A, 0x7F),
+            (0xFF1B, 0xFF),
+            (0xFF1C, 0x9F),
+            (0xFF1D, 0xFF),
+            (0xFF1E, 0xBF),
+            (0xFF20, 0xFF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (0xFF23, 0xBF),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+        ];
+        for (index, value) in apu {
+            self.write(index, value);
+        }
+        self.write(0xFF0F, 0x01);
+        self.write(0xFF47, 0xFC);
+        self.write(0xFF40, 0x91);
+        if !model.is_gbc() {
+            self.skip_logo();
+        }
+        if self.mode == GameBoyMode::GBCCompat {
+            self.skip_compat_boot();
+        }
+        let counter = match model {
+            Model::DMG0 => 0x182C,
+            Model::DMG | Model::MGB => 0xABC8,
+            Model::SGB | Model::SGB2 => 0xD85C,
+            Model::CGB => 0x2674,
+            Model::AGB => 0x2678,
+        };
+        self.timer.set_counter(counter);
+    }
+    // the logo from the cartridge header in tiles 1~24 and the (R) in tile 25, in the middle
+    // of the map
+    fn skip_logo(&mut self) {
+        let logo: Vec<u8> = (0x0104..=0x0133)
+            .map(|index| self.cartridge.content.get(index))
+            .collect();
+        // every nibble is stretched to a byte, twice for two rows
+        for (index, byte) in logo.iter().enumerate() {
+            for (half, nibble) in [byte >> 4, byte & 0x0F].iter().enumerate() {
+                let mut row = 0;
+                for bit in (0..4).rev() {
+                    if nibble >> bit & 1 != 0 {
+                        row |= 0b11 << (bit * 2);
+                    }
+                }
+                let address = 0x8010 + index as u16 * 8 + half as u16 * 4;
+                self.ppu.set(address, row);
+                self.ppu.set(address + 2, row);
+            }
+        }
+        let registered = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+        for (index, row) in registered.iter().enumerate() {
+            self.ppu.set(0x8190 + index as u16 * 2, *row);
+        }
+        for tile in 1..=12 {
+            self.ppu.set(0x9903 + tile, tile as u8);
+            self.ppu.set(0x9923 + tile, tile as u8 + 12);
+        }
+        self.ppu.set(0x9910, 0x19);
+    }
+    // what the GBC boot rom leaves for a GB cartridge, with the colors it looks up for the title
+    fn skip_compat_boot(&mut self) {
+        self.other.set(0xFF4C, 0x04);
+        self.ppu.set(0xFF6C, 0x01);
+        let id = compat::title_palette_id(|index| self.cartridge.content.get(index));
+        self.set_compat_palettes(id);
+    }
+    // BG palette 0 and OBJ palettes 0 and 1, the only ones a GB cartridge uses
+    pub(crate) fn set_compat_palettes(&mut self, id: u8) {
+        let [bg, obj0, obj1] = compat::palettes(id);
+        self.ppu.set(0xFF68, 0x80);
+        self.ppu.set(0xFF6A, 0x80);
+        for color in bg {
+            let [low, high] = color.to_le_bytes();
+            self.ppu.set(0xFF69, low);
+            self.ppu.set(0xFF69, high);
+        }
+        for color in obj0.iter().chain(obj1.iter()) {
+            let [low, high] = color.to_le_bytes();
+            self.ppu.set(0xFF6B, low);
+            self.ppu.set(0xFF6B, high);
+        }
+    }
     pub fn is_boot(&self) -> bool {
-        let v = self.get(0xFF50);
-        v == 0
+        self.other.get(0xFF50) & 0x01 == 0
     }
-    fn dma(&mut self, value: u8) {
-        if value > 0xdf {
-            return;
+    fn set_mode(&mut self, mode: GameBoyMode) {
+        self.mode = mode;
+        self.ppu.set_gameboy_mode(mode);
+        // only GBC switches wram banks, 0xD000~0xDFFF stays on bank 1 otherwise
+        if mode != GameBoyMode::GBC {
+            self.wram.bank = 1;
         }
-        for index in 0x00..=0x9F {
-            let source = ((value as u16) << 8) | index;
-            let destination = 0xFE00 | index;
-            let source_v = self.get(source);
-            self.set(destination, source_v);
+    }
+    // rom bank for 0x0000~0x7FFF, cartridge ram bank for 0xA000~0xBFFF, vram and wram bank for
+    // 0x8000~0x9FFF and 0xD000~0xDFFF on GBC
+    pub fn bank(&self, index: u16) -> Option<usize> {
+        match index {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF => Some(self.cartridge.content.rom_bank()),
+            0x8000..=0x9FFF if self.mode == GameBoyMode::GBC => {
+                Some((self.ppu.get(0xFF4F) & 0x01) as usize)
+            }
+            0xA000..=0xBFFF => Some(self.cartridge.content.ram_bank()),
+            0xD000..=0xDFFF if self.mode == GameBoyMode::GBC => {
+                Some(self.wram.bank.max(1) as usize)
+            }
+            _ => None,
         }
     }
-    pub fn bind_event(&mut self, index: u16, value: u8) {
+    // the peeks look at memory without the side effects of a cpu access, banks that don't
+    // exist read 0xFF
+    pub fn peek_rom(&self, bank: usize, index: u16) -> u8 {
+        let rom = self.cartridge.content.rom();
+        let offset = bank * 0x4000 + (index & 0x3FFF) as usize;
+        rom.get(offset).copied().unwrap_or(0xFF)
+    }
+    pub fn peek_vram(&self, bank: usize, index: u16) -> u8 {
+        self.ppu.peek_vram(bank, index)
+    }
+    // bank 0 is 0xC000~0xCFFF, the others are the banks of 0xD000~0xDFFF
+    pub fn peek_wram(&self, bank: usize, index: u16) -> u8 {
+        let offset = index as usize & 0x0FFF;
+        let banks = if self.mode == GameBoyMode::GBC { 8 } else { 2 };
+        if bank < banks {
+            self.wram.memory[bank * 0x1000 + offset]
+        } else {
+            0xFF
+        }
+    }
+    pub fn peek_sram(&self, bank: usize, index: u16) -> u8 {
+        let ram = self.cartridge.content.ram();
+        let offset = bank * 0x2000 + (index & 0x1FFF) as usize;
+        ram.get(offset).copied().unwrap_or(0xFF)
+    }
+    // changes the memory behind index, ignoring the mbc and whether its ram is enabled. rom
+    // stays as it is. I/O registers keep the value without what writing it does: no DMA, timer
+    // reset, serial log, sound trigger, STAT interrupt or palette index step. FF50 and FF55,
+    // whose value is the effect, and the sound power switch FF26 are left alone.
+    pub fn store(&mut self, index: u16, value: u8) {
         match index {
-            0xFF02 => {
-                if value == 0x81 {
-                    let v = self.get(0xFF01);
-                    self.log_msg.push(v);
+            0x0000..=0x7FFF => {}
+            0xFF02 => self.other.set(index, value),
+            0xFF04..=0xFF07 => self.timer.store(index, value),
+            0xFF26 | 0xFF50 | 0xFF55 => {}
+            0xFF10..=0xFF3F => self.apu.store(index, value),
+            0xFF41 | 0xFF45 => self.ppu.store(index, value),
+            0xFF69 | 0xFF6B if self.mode == GameBoyMode::GBC => self.ppu.store(index, value),
+            0xFF46 => self.oam_dma.register = value,
+            0xA000..=0xBFFF => {
+                let offset = self.cartridge.content.ram_bank() * 0x2000 + (index - 0xA000) as usize;
+                if let Some(byte) = self.cartridge.content.ram_mut().get_mut(offset) {
+                    *byte = value;
                 }
             }
-            0xFF46 => {
-                self.dma(value);
+            0xE000..=0xFDFF => self.store(index - 0x2000, value),
+            _ => self.write(index, value),
+        }
+    }
+    fn hook(&self, kind: HookKind, address: u16, value: u8) {
+        if self.hooks.wants(kind, address) {
+            self.hooks.call(&HookEvent {
+                kind,
+                address,
+                value,
+                bank: self.bank(address),
+            });
+        }
+    }
+    fn cpu_read(&self, index: u16, kind: HookKind) -> u8 {
+        let value = if self.dma_conflict(index) {
+            if (0xFE00..=0xFEFF).contains(&index) {
+                0xFF
+            } else {
+                self.oam_dma.byte
             }
-            _ => {}
+        } else if self.ppu.is_locked(index) {
+            0xFF
+        } else {
+            self.read(index)
         };
+        let access = match kind {
+            HookKind::Execute => Access::Fetch,
+            _ => Access::Read,
+        };
+        self.access_log.push(index, value, access);
+        self.hook(kind, index, value);
+        value
     }
 }
+// the cpu side, which competes with OAM DMA for the buses
 impl Memory for Mmu {
     fn get(&self, index: u16) -> u8 {
+        self.cpu_read(index, HookKind::Read)
+    }
+    fn set(&mut self, index: u16, value: u8) {
+        self.access_log.push(index, value, Access::Write);
+        if !self.dma_conflict(index) && !self.ppu.is_locked(index) {
+            self.write(index, value);
+        }
+        self.hook(HookKind::Write, index, value);
+    }
+}
+
+impl Bus for Mmu {
+    fn tick(&mut self) {
+        let div = self.timer.get(0xFF04);
+        self.timer.tick();
+        self.trick_frame_sequencer(div);
+        self.step_oam_dma();
+    }
+    fn fetch(&self, index: u16) -> u8 {
+        self.cpu_read(index, HookKind::Execute)
+    }
+    fn peek(&self, index: u16) -> u8 {
+        self.read(index)
+    }
+    fn poke(&mut self, index: u16, value: u8) {
+        self.write(index, value);
+    }
+    fn double_speed(&self) -> bool {
+        self.speed.current_speed
+    }
+    fn set_double_speed(&mut self, double_speed: bool) {
+        self.speed.current_speed = double_speed;
+    }
+    fn switch_speed(&mut self) -> bool {
+        self.speed.switch()
+    }
+    fn reset_div(&mut self) {
+        self.write(0xFF04, 0);
+    }
+    fn stall(&mut self) {
+        self.step_oam_dma();
+    }
+    fn joypad_low(&self) -> bool {
+        self.joypad.get(0xFF00) & 0x0F != 0x0F
+    }
+    fn dma_stall(&mut self) -> u32 {
+        if self.mode == GameBoyMode::GBC {
+            self.step_hdma()
+        } else {
+            0
+        }
+    }
+}
+
+impl Mmu {
+    // the apu frame sequencer steps when DIV bit 4 (bit 5 in double speed) falls, so DIV
+    // writes delay or advance it
+    fn trick_frame_sequencer(&mut self, div: u8) {
+        let bit = if self.speed.current_speed { 5 } else { 4 };
+        if check_bit(div, bit) && !check_bit(self.timer.get(0xFF04), bit) {
+            self.apu.trick_frame_sequencer();
+        }
+    }
+    // one byte every machine cycle, after a setup cycle
+    fn step_oam_dma(&mut self) {
+        if self.oam_dma.copied == Some(0xA0) {
+            self.oam_dma.copied = None;
+        }
+        // a restart keeps the old transfer running through its setup cycle
+        if let Some(source) = self.oam_dma.starting.take() {
+            self.oam_dma.source = source;
+            self.oam_dma.copied = Some(0);
+        }
+        self.oam_dma.starting = self.oam_dma.pending.take();
+        if let Some(copied) = self.oam_dma.copied {
+            let source = self.oam_dma.source + copied;
+            // sources past WRAM read its echo
+            let source = if source >= 0xE000 {
+                source - 0x2000
+            } else {
+                source
+            };
+            let value = self.read(source);
+            self.ppu.set(0xFE00 + copied, value);
+            self.oam_dma.byte = value;
+            self.oam_dma.copied = Some(copied + 1);
+        }
+    }
+    // the cpu can only reach HRAM and the registers while OAM DMA runs, reads from the bus it
+    // uses see the transferred byte and writes get lost
+    fn dma_conflict(&self, index: u16) -> bool {
+        if !self.oam_dma.active() {
+            return false;
+        }
+        let bus = |index: u16| match index {
+            0x8000..=0x9FFF => 1,
+            0xC000..=0xFDFF if self.mode == GameBoyMode::GBC => 2,
+            _ => 0,
+        };
+        match index {
+            0xFF00..=0xFFFF => false,
+            0xFE00..=0xFEFF => true,
+            _ => bus(index) == bus(self.oam_dma.source),
+        }
+    }
+    // whole blocks at once, the cpu waits for them
+    fn step_hdma(&mut self) -> u32 {
+        let hblank = !self.ppu.lcdc.lcd_ppu_enable || self.ppu.stat.mode_flag as u8 == 0;
+        let blocks = self.hdma.blocks_due(hblank);
+        let mut cycles = 0;
+        for _ in 0..blocks {
+            let (source, destination) = self.hdma.take_block();
+            for index in 0..0x10 {
+                let value = match source.wrapping_add(index) {
+                    // VRAM can't be a source and there's nothing past WRAM
+                    0x8000..=0x9FFF | 0xE000..=0xFFFF => 0xFF,
+                    source => self.read(source),
+                };
+                self.ppu.set(destination + index, value);
+            }
+            cycles += BLOCK_CYCLES;
+            if !self.hdma.active {
+                break;
+            }
+        }
+        cycles
+    }
+    // read without recording the access
+    pub(crate) fn read(&self, index: u16) -> u8 {
+        match index {
+            0xFF00..=0xFF7F => self.read_map(index) | self.unused_bits(index),
+            _ => self.read_map(index),
+        }
+    }
+    fn read_map(&self, index: u16) -> u8 {
         match index {
             0x0000..=0x00FF => {
                 if self.is_boot() {
@@ -318,12 +621,23 @@ impl Memory for Mmu {
             0x0900..=0x7FFF => self.cartridge.content.get(index),
             0x8000..=0x9FFF => self.ppu.get(index),
             0xA000..=0xBFFF => self.cartridge.content.get(index),
+            0xE000..=0xFDFF => self.read_map(index - 0x2000),
             0xFE00..=0xFE9F => self.ppu.get(index),
+            0xFEA0..=0xFEFF => {
+                // CGB repeats the high nibble of the low address byte, DMG reads 0
+                if self.mode != GameBoyMode::GB {
+                    (index as u8 & 0xF0) | (index as u8 >> 4)
+                } else {
+                    0x00
+                }
+            }
             0xFF00 => self.joypad.get(index),
+            0xFF04..=0xFF07 => self.timer.get(index),
+            0xFF46 => self.oam_dma.get(index),
             0xFF0F => {
                 let vblank_flag = self.ppu.interrupt_flag_vblank;
                 let lcdstat_flag = self.ppu.interrupt_flag_lcdstat;
-                let timer_flag = self.timer_flag;
+                let timer_flag = self.timer.interrupt_flag;
                 let serial_flag = self.serial_flag;
                 let joypad_flag = self.joypad.interrupt_flag;
                 (vblank_flag as u8) << 0
@@ -333,33 +647,43 @@ impl Memory for Mmu {
                     | (joypad_flag as u8) << 4
                     | 0b11100000
             }
-            0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF4F => self.ppu.get(index),
-            0xFF4D => {
+            0xFF10..=0xFF3F | 0xFF76 | 0xFF77 => self.apu.get(index),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.get(index),
+            0xFF4F => {
                 if self.mode == GameBoyMode::GBC {
-                    self.speed.get(index)
+                    self.ppu.get(index)
                 } else {
-                    self.other.get(index)
+                    0x00
                 }
             }
-            0xFF51..=0xFF55 => {
+            0xFF4D => {
                 if self.mode == GameBoyMode::GBC {
-                    self.hdma.get(index)
+                    self.speed.get(index)
                 } else {
                     self.other.get(index)
                 }
             }
-            0xFF68 | 0xFF69 | 0xFF6A | 0xFF6B => {
+            0xFF51..=0xFF55 => {
                 if self.mode == GameBoyMode::GBC {
-                    self.ppu.get(index)
+                    self.hdma.get(index)
                 } else {
                     self.other.get(index)
                 }
             }
-            0xFF70 | 0xC000..=0xDFFF => {
+            0xFF68 | 0xFF69 | 0xFF6A | 0xFF6B => match self.mode {
+                GameBoyMode::GBC => self.ppu.get(index),
+                // GB software still sees where the boot rom left the indexes, not the colors
+                GameBoyMode::GBCCompat if index & 1 == 0 => self.ppu.get(index),
+                GameBoyMode::GBCCompat => 0xFF,
+                GameBoyMode::GB => self.other.get(index),
+            },
+            0xFF6C if self.mode != GameBoyMode::GB => self.ppu.get(index),
+            0xC000..=0xDFFF => self.wram.get(index),
+            0xFF70 => {
                 if self.mode == GameBoyMode::GBC {
                     self.wram.get(index)
                 } else {
-                    self.other.get(index)
+                    0xFF
                 }
             }
             0xFF74 => {
@@ -372,21 +696,78 @@ impl Memory for Mmu {
             _ => self.other.get(index),
         }
     }
-    fn set(&mut self, index: u16, value: u8) {
+    // bits of the I/O registers that always read 1, all of them when nothing is mapped
+    fn unused_bits(&self, index: u16) -> u8 {
+        let gbc = self.mode == GameBoyMode::GBC;
+        // some of them don't go away in GBCCompat
+        let gbc_hardware = self.mode != GameBoyMode::GB;
+        match index {
+            0xFF00 => 0xC0,
+            0xFF01 => 0x00,
+            0xFF02 => {
+                if gbc {
+                    0x7C
+                } else {
+                    0x7E
+                }
+            }
+            // the timer, IF and the apu mask their own registers
+            0xFF04..=0xFF07 | 0xFF0F | 0xFF10..=0xFF3F => 0x00,
+            0xFF41 => 0x80,
+            0xFF40 | 0xFF42..=0xFF4B => 0x00,
+            0xFF4D if gbc => 0x7E,
+            0xFF4F if gbc_hardware => 0xFE,
+            0xFF51..=0xFF55 if gbc => 0x00,
+            0xFF56 if gbc => 0x3C,
+            0xFF68 | 0xFF6A if gbc_hardware => 0x40,
+            0xFF69 | 0xFF6B if gbc => 0x00,
+            0xFF6C if gbc_hardware => 0xFE,
+            0xFF70 if gbc => 0xF8,
+            0xFF72 | 0xFF73 | 0xFF76 | 0xFF77 if gbc_hardware => 0x00,
+            0xFF74 if gbc => 0x00,
+            0xFF75 if gbc_hardware => 0x8F,
+            _ => 0xFF,
+        }
+    }
+    // write without recording the access
+    pub(crate) fn write(&mut self, index: u16, value: u8) {
         match index {
             0x0000..=0x7FFF => self.cartridge.content.set(index, value),
             0x8000..=0x9FFF => self.ppu.set(index, value),
             0xA000..=0xBFFF => self.cartridge.content.set(index, value),
+            0xE000..=0xFDFF => self.write(index - 0x2000, value),
             0xFE00..=0xFE9F => self.ppu.set(index, value),
+            0xFEA0..=0xFEFF => {}
             0xFF00 => self.joypad.set(index, value),
+            0xFF02 => {
+                // the serial port only carries the text test roms print
+                if value == 0x81 {
+                    let v = self.other.get(0xFF01);
+                    self.log_msg.push(v);
+                }
+                self.other.set(index, value);
+            }
+            0xFF04..=0xFF07 => {
+                let div = self.timer.get(0xFF04);
+                self.timer.set(index, value);
+                self.trick_frame_sequencer(div);
+            }
+            0xFF46 => self.oam_dma.set(index, value),
             0xFF0F => {
                 self.ppu.interrupt_flag_vblank = value & 0b0000_0001 > 0;
                 self.ppu.interrupt_flag_lcdstat = value & 0b0000_0010 > 0;
-                self.timer_flag = value & 0b0000_0100 > 0;
+                self.timer.interrupt_flag = value & 0b0000_0100 > 0;
                 self.serial_flag = value & 0b0000_1000 > 0;
                 self.joypad.interrupt_flag = value & 0b0001_0000 > 0;
             }
-            0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF4F => self.ppu.set(index, value),
+            0xFF10..=0xFF3F => self.apu.set(index, value),
+            0xFF76 | 0xFF77 => {}
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.set(index, value),
+            0xFF4F => {
+                if self.mode == GameBoyMode::GBC {
+                    self.ppu.set(index, value)
+                }
+            }
             0xFF4D => {
                 if self.mode == GameBoyMode::GBC {
                     self.speed.set(index, value)
@@ -401,18 +782,36 @@ impl Memory for Mmu {
                     self.other.set(index, value)
                 }
             }
-            0xFF68 | 0xFF69 | 0xFF6A | 0xFF6B => {
-                if self.mode == GameBoyMode::GBC {
-                    self.ppu.set(index, value)
-                } else {
-                    self.other.set(index, value)
+            0xFF68 | 0xFF69 | 0xFF6A | 0xFF6B => match self.mode {
+                GameBoyMode::GBC => self.ppu.set(index, value),
+                GameBoyMode::GBCCompat => {}
+                GameBoyMode::GB => self.other.set(index, value),
+            },
+            // KEY0 and OPRI are set up by the boot rom for the cartridge and locked after it
+            0xFF4C => {
+                if self.mode == GameBoyMode::GBC && self.is_boot() {
+                    self.other.set(index, value);
                 }
             }
-            0xFF70 | 0xC000..=0xDFFF => {
+            0xFF6C => {
+                if self.mode != GameBoyMode::GB && self.is_boot() {
+                    self.ppu.set(index, value);
+                }
+            }
+            // the boot rom is done with bit 0, there is no way back to it
+            0xFF50 => {
+                if self.is_boot() && value & 0x01 != 0 {
+                    // a KEY0 with bit 2 or 3 set drops the GBC features
+                    if self.mode == GameBoyMode::GBC && self.other.get(0xFF4C) & 0x0C != 0 {
+                        self.set_mode(GameBoyMode::GBCCompat);
+                    }
+                    self.other.set(index, value);
+                }
+            }
+            0xC000..=0xDFFF => self.wram.set(index, value),
+            0xFF70 => {
                 if self.mode == GameBoyMode::GBC {
                     self.wram.set(index, value)
-                } else {
-                    self.other.set(index, value)
                 }
             }
             0xFF74 => {
@@ -423,7 +822,6 @@ impl Memory for Mmu {
             }
             _ => self.other.set(index, value),
         }
-        self.bind_event(index, value);
     }
 }
 
@@ -443,6 +841,7 @@ impl Default for Mmu {
         let mode = GameBoyMode::GB;
         let joypad = JoyPad::new();
         let ppu = PpuMmu::new(mode);
+        let apu = Apu::new(mode);
         let hdma = HDMA::new();
         let speed = Speed::new();
         let wram = WRAM::new();
@@ -456,12 +855,16 @@ impl Default for Mmu {
             other,
             joypad,
             ppu,
+            apu,
             wram,
             hdma,
             speed,
-            timer_flag: false,
+            timer: Timer::new(),
+            oam_dma: OamDma::new(),
             serial_flag: false,
             log_msg: vec![],
+            access_log: AccessLog::default(),
+            hooks: Hooks::default(),
         }
     }
 }
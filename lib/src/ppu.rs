@@ -1,23 +1,99 @@
 use crate::gameboy_mode::GameBoyMode;
 use crate::memory::Memory;
-use crate::mmu::Mmu;
-use crate::ppu::FetcherStatus::{GetTile, GetTileDataHigh, GetTileDataLow};
+use crate::ppu::FetcherStatus::{DataHigh, DataLow, Tile};
 use crate::ppu::PixelType::{Sprite, Window, BG};
 use crate::ppu::PpuStatus::{Drawing, HBlank, OAMScan, VBlank};
 use crate::util::check_bit;
 // use log::info;
 use crate::big_array::BigArray;
 use std::collections::VecDeque;
-use std::{cell::RefCell, rc::Rc};
 
 pub const WIDTH: usize = 160;
 pub const HEIGHT: usize = 144;
 
-enum Color {
-    WHITE = 0xE0F8D0,
-    LightGray = 0x88C070,
-    DarkGray = 0x346856,
-    BlackGray = 0x081820,
+// in dots, 4 of them to a machine cycle at single speed
+const LINE_DOTS: u32 = 456;
+const LINES: u8 = 154;
+const OAM_SCAN_DOTS: u32 = 85;
+// STAT shows the OAM scan a machine cycle after its interrupt, intr_2_0_timing and
+// intr_2_mode0_timing fail a dot either way
+const OAM_SCAN_DELAY: u32 = 4;
+// later on the first line of a frame, intr_1_2_timing-GS fails with OAM_SCAN_DELAY
+const LINE0_DELAY: u32 = 9;
+// LY moves on some way into the line, hblank_ly_scx_timing-GS fails when it moves at dot 0
+const LY_DELAY_DOTS: u32 = 13;
+// the shortest drawing, two tile fetches and the 160 pixels, with no scroll, window or objects
+const DRAWING_DOTS: u32 = 172;
+// the HBlank interrupt lags behind STAT the other way, hblank_ly_scx_timing-GS fails a dot
+// either way
+const HBLANK_INTERRUPT_DELAY: u32 = 3;
+// LY shows 153 for one machine cycle at the start of the last line
+const LY_153_DOTS: u32 = LY_DELAY_DOTS + 4;
+// the LCD comes on this far into line 0, intr_2_mode0_timing_sprites fails a dot either way
+const LCD_ON_DOTS: u32 = 20;
+
+// the 4 shades of a DMG palette as 0xRRGGBB, from lightest to darkest
+pub type Shades = [u32; 4];
+
+// colors for DMG games, separate ones for the background and the two object palettes like the
+// CGB boot rom gives them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DmgPalette {
+    pub bg: Shades,
+    pub obj0: Shades,
+    pub obj1: Shades,
+}
+
+impl DmgPalette {
+    pub const GREEN: Self = Self::uniform([0xE0F8D0, 0x88C070, 0x346856, 0x081820]);
+    pub const GRAYSCALE: Self = Self::uniform([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]);
+    pub const POCKET: Self = Self::uniform([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]);
+    pub const fn uniform(shades: Shades) -> Self {
+        Self {
+            bg: shades,
+            obj0: shades,
+            obj1: shades,
+        }
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        Self::GREEN
+    }
+}
+
+// green, grayscale or pocket, or 4 hex colors for all palettes or 12 for bg, obj0 and obj1
+impl std::str::FromStr for DmgPalette {
+    type Err = String;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "green" => return Ok(Self::GREEN),
+            "grayscale" => return Ok(Self::GRAYSCALE),
+            "pocket" => return Ok(Self::POCKET),
+            _ => {}
+        }
+        let colors = text
+            .split(',')
+            .map(|color| {
+                let color = color.trim().trim_start_matches('#');
+                match u32::from_str_radix(color, 16) {
+                    Ok(value) if color.len() == 6 => Ok(value),
+                    _ => Err(format!("{} is not a RRGGBB color", color)),
+                }
+            })
+            .collect::<Result<Vec<u32>, String>>()?;
+        let shades = |index: usize| [0, 1, 2, 3].map(|shade| colors[index * 4 + shade]);
+        match colors.len() {
+            4 => Ok(Self::uniform(shades(0))),
+            12 => Ok(Self {
+                bg: shades(0),
+                obj0: shades(1),
+                obj1: shades(2),
+            }),
+            _ => Err(format!("unknown palette {}", text)),
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -35,6 +111,8 @@ struct Pixel {
     bg_window_over_obj: bool,
     oam_priority: usize,
     bg_to_oam: bool,
+    // the object uses OBP1
+    obj1: bool,
 }
 
 impl Default for Pixel {
@@ -46,26 +124,27 @@ impl Default for Pixel {
             bg_window_over_obj: false,
             oam_priority: 40,
             bg_to_oam: false,
+            obj1: false,
         }
     }
 }
 
 enum FetcherStatus {
-    GetTile,
-    GetTileDataLow,
-    GetTileDataHigh,
+    Tile,
+    DataLow,
+    DataHigh,
 }
 
 trait Fetcher {
-    fn new(mmu: Rc<RefCell<Mmu>>, scan_x: u8, scan_y: u8) -> Self
+    fn new(mode: GameBoyMode, scan_x: u8, scan_y: u8) -> Self
     where
         Self: Sized;
-    fn trick(&mut self);
-    fn get_tile(&mut self) -> u16;
-    fn get_tile_data_low(&self) -> u8;
-    fn get_tile_data_high(&self) -> u8;
-    fn get_buffer(&mut self) -> Vec<Pixel>;
-    fn get_color_index(&self, pvalue: u8) -> u8;
+    fn trick(&mut self, mmu: &PpuMmu);
+    fn get_tile(&mut self, mmu: &PpuMmu) -> u16;
+    fn get_tile_data_low(&self, mmu: &PpuMmu) -> u8;
+    fn get_tile_data_high(&self, mmu: &PpuMmu) -> u8;
+    fn get_buffer(&mut self, mmu: &PpuMmu) -> Vec<Pixel>;
+    fn get_color_index(&self, mmu: &PpuMmu, pvalue: u8) -> u8;
     fn buffer(&self) -> &[Pixel];
 }
 
@@ -101,7 +180,6 @@ struct FetcherBg {
     scy: u8,
     bg_map_attr: BGMapAttr,
     cycles: u16,
-    mmu: Rc<RefCell<Mmu>>,
     status: FetcherStatus,
     tile_index: u16,
     tile_data_low: u8,
@@ -109,8 +187,7 @@ struct FetcherBg {
     buffer: Vec<Pixel>,
 }
 impl Fetcher for FetcherBg {
-    fn new(mmu: Rc<RefCell<Mmu>>, scan_x: u8, scan_y: u8) -> Self {
-        let mode = mmu.borrow().mode;
+    fn new(mode: GameBoyMode, scan_x: u8, scan_y: u8) -> Self {
         Self {
             mode,
             scan_x,
@@ -118,115 +195,94 @@ impl Fetcher for FetcherBg {
             scx: 0,
             scy: 0,
             bg_map_attr: BGMapAttr::from(0),
-            mmu,
             cycles: 0,
-            status: GetTile,
+            status: Tile,
             tile_index: 0,
             tile_data_low: 0,
             tile_data_high: 0,
             buffer: Vec::with_capacity(8),
         }
     }
-    fn trick(&mut self) {
+    fn trick(&mut self, mmu: &PpuMmu) {
         if self.cycles == 1 {
             self.cycles = 0;
             return;
         }
         self.cycles += 1;
         match self.status {
-            GetTile => {
-                self.tile_index = self.get_tile();
-                self.status = GetTileDataLow;
+            Tile => {
+                self.tile_index = self.get_tile(mmu);
+                self.status = DataLow;
             }
-            GetTileDataLow => {
-                self.tile_data_low = self.get_tile_data_low();
-                self.status = GetTileDataHigh;
+            DataLow => {
+                self.tile_data_low = self.get_tile_data_low(mmu);
+                self.status = DataHigh;
             }
-            GetTileDataHigh => {
-                self.tile_data_high = self.get_tile_data_high();
-                self.buffer = self.get_buffer();
-                self.status = GetTile;
+            DataHigh => {
+                self.tile_data_high = self.get_tile_data_high(mmu);
+                self.buffer = self.get_buffer(mmu);
+                self.status = Tile;
             }
         }
     }
-    fn get_tile(&mut self) -> u16 {
-        let bg_window_tile_data_area = self.mmu.borrow().ppu.lcdc.bg_window_tile_data_area;
-        let bg_tile_map_area = self.mmu.borrow().ppu.lcdc.bg_tile_map_area;
+    fn get_tile(&mut self, mmu: &PpuMmu) -> u16 {
+        let bg_window_tile_data_area = mmu.lcdc.bg_window_tile_data_area;
+        let bg_tile_map_area = mmu.lcdc.bg_tile_map_area;
         let bg_map_start: u16 = match bg_tile_map_area {
             true => 0x9C00,
             false => 0x9800,
         };
-        self.scy = self.mmu.borrow().ppu.scy;
-        self.scx = self.mmu.borrow().ppu.scx;
+        self.scy = mmu.scy;
+        self.scx = mmu.scx;
         let bg_map_x = (self.scan_x as u16 + self.scx as u16) % 256 / 8;
         let bg_map_y = (self.scan_y as u16 + self.scy as u16) % 256 / 8;
         let bg_map_index = bg_map_x + bg_map_y * 32;
-        let bg_map_byte = self
-            .mmu
-            .borrow()
-            .ppu
-            .vram
-            .get_by_bank(bg_map_start + bg_map_index, false);
+        let bg_map_byte = mmu.vram.get_by_bank(bg_map_start + bg_map_index, false);
         let tile_index: u16 = if bg_window_tile_data_area {
             0x8000 + bg_map_byte as u16 * 8 * 2
         } else {
             (0x9000 as i32 + (bg_map_byte as i8) as i32 * 8 * 2) as u16
         };
         if self.mode == GameBoyMode::GBC {
-            let bg_map_attr_val = self
-                .mmu
-                .borrow()
-                .ppu
-                .vram
-                .get_by_bank(bg_map_start + bg_map_index, true);
+            let bg_map_attr_val = mmu.vram.get_by_bank(bg_map_start + bg_map_index, true);
             self.bg_map_attr = BGMapAttr::from(bg_map_attr_val);
         }
         tile_index
     }
-    fn get_tile_data_low(&self) -> u8 {
+    fn get_tile_data_low(&self, mmu: &PpuMmu) -> u8 {
         let tile_index = self.tile_index;
         let mut tile_pixel_y = (self.scan_y as u16 + self.scy as u16) % 8;
         if self.mode == GameBoyMode::GBC {
             if self.bg_map_attr.y_flip {
                 tile_pixel_y = (8 - 1) - tile_pixel_y;
             }
-            self.mmu
-                .borrow()
-                .ppu
-                .vram
+            mmu.vram
                 .get_by_bank(tile_index + tile_pixel_y * 2, self.bg_map_attr.vram_bank)
         } else {
-            self.mmu
-                .borrow()
-                .ppu
-                .vram
-                .get_by_bank(tile_index + tile_pixel_y * 2, false)
+            mmu.vram.get_by_bank(tile_index + tile_pixel_y * 2, false)
         }
     }
-    fn get_tile_data_high(&self) -> u8 {
+    fn get_tile_data_high(&self, mmu: &PpuMmu) -> u8 {
         let tile_index = self.tile_index;
         let mut tile_pixel_y = (self.scan_y as u16 + self.scy as u16) % 8;
         if self.mode == GameBoyMode::GBC {
             if self.bg_map_attr.y_flip {
                 tile_pixel_y = (8 - 1) - tile_pixel_y;
             }
-            self.mmu.borrow().ppu.vram.get_by_bank(
+            mmu.vram.get_by_bank(
                 tile_index + tile_pixel_y * 2 + 1,
                 self.bg_map_attr.vram_bank,
             )
         } else {
-            self.mmu
-                .borrow()
-                .ppu
-                .vram
+            mmu.vram
                 .get_by_bank(tile_index + tile_pixel_y * 2 + 1, false)
         }
     }
-    fn get_buffer(&mut self) -> Vec<Pixel> {
+    fn get_buffer(&mut self, mmu: &PpuMmu) -> Vec<Pixel> {
         let mut result = Vec::new();
         let mut get_pixel_bit: Box<dyn Fn(u8) -> u8> = Box::new(|index: u8| 8 - index - 1);
         let buffer_index_start = (self.scan_x as u16 + self.scx as u16) % 8;
-        let bg_window_enable = self.mmu.borrow().ppu.lcdc.bg_window_enable;
+        let bg_window_enable = mmu.lcdc.bg_window_enable;
         if (self.mode == GameBoyMode::GBC) && self.bg_map_attr.x_flip {
             get_pixel_bit = Box::new(|index: u8| index);
         }
@@ -235,8 +291,8 @@ impl Fetcher for FetcherBg {
             let pixel_low = check_bit(self.tile_data_low, pixel_bit as u8);
             let pixel_high = check_bit(self.tile_data_high, pixel_bit as u8);
             let pvalue = (pixel_low as u8) | ((pixel_high as u8) << 1);
-            let pcolor = self.get_color_index(pvalue);
-            let pixel = if self.mode == GameBoyMode::GB {
+            let pcolor = self.get_color_index(mmu, pvalue);
+            let pixel = if self.mode != GameBoyMode::GBC {
                 if !bg_window_enable {
                     Pixel {
                         ptype: BG,
@@ -265,12 +321,12 @@ impl Fetcher for FetcherBg {
         }
         result
     }
-    fn get_color_index(&self, pvalue: u8) -> u8 {
+    fn get_color_index(&self, mmu: &PpuMmu, pvalue: u8) -> u8 {
         if self.mode == GameBoyMode::GBC {
             let bg_palette = self.bg_map_attr.bg_palette;
             bg_palette * 4 * 2 + pvalue * 2
         } else {
-            let palette = self.mmu.borrow().ppu.bgp;
+            let palette = mmu.bgp;
             match pvalue {
                 0 => palette & 0b11,
                 1 => (palette & 0b1100) >> 2,
@@ -295,7 +351,6 @@ struct FetcherWindow {
     window_internal_line_index: u8,
     bg_map_attr: BGMapAttr,
     cycles: u16,
-    mmu: Rc<RefCell<Mmu>>,
     status: FetcherStatus,
     tile_index: u16,
     tile_data_low: u8,
@@ -308,8 +363,7 @@ impl FetcherWindow {
     }
 }
 impl Fetcher for FetcherWindow {
-    fn new(mmu: Rc<RefCell<Mmu>>, scan_x: u8, _: u8) -> Self {
-        let mode = mmu.borrow().mode;
+    fn new(mode: GameBoyMode, scan_x: u8, _: u8) -> Self {
         Self {
             mode,
             scan_x,
@@ -317,56 +371,50 @@ impl Fetcher for FetcherWindow {
             wy: 0,
             window_internal_line_index: 0,
             bg_map_attr: BGMapAttr::from(0),
-            mmu,
             cycles: 0,
-            status: GetTile,
+            status: Tile,
             tile_index: 0,
             tile_data_low: 0,
             tile_data_high: 0,
             buffer: Vec::with_capacity(8),
         }
     }
-    fn trick(&mut self) {
+    fn trick(&mut self, mmu: &PpuMmu) {
         if self.cycles == 1 {
             self.cycles = 0;
             return;
         }
         self.cycles += 1;
         match self.status {
-            GetTile => {
-                self.tile_index = self.get_tile();
-                self.status = GetTileDataLow;
+            Tile => {
+                self.tile_index = self.get_tile(mmu);
+                self.status = DataLow;
             }
-            GetTileDataLow => {
-                self.tile_data_low = self.get_tile_data_low();
-                self.status = GetTileDataHigh;
+            DataLow => {
+                self.tile_data_low = self.get_tile_data_low(mmu);
+                self.status = DataHigh;
             }
-            GetTileDataHigh => {
-                self.tile_data_high = self.get_tile_data_high();
-                self.buffer = self.get_buffer();
-                self.status = GetTile;
+            DataHigh => {
+                self.tile_data_high = self.get_tile_data_high(mmu);
+                self.buffer = self.get_buffer(mmu);
+                self.status = Tile;
             }
         }
     }
-    fn get_tile(&mut self) -> u16 {
-        let bg_window_tile_data_area = self.mmu.borrow().ppu.lcdc.bg_window_tile_data_area;
-        let window_tile_map_area = self.mmu.borrow().ppu.lcdc.window_tile_map_area;
+    fn get_tile(&mut self, mmu: &PpuMmu) -> u16 {
+        let bg_window_tile_data_area = mmu.lcdc.bg_window_tile_data_area;
+        let window_tile_map_area = mmu.lcdc.window_tile_map_area;
         let window_map_start: u16 = match window_tile_map_area {
             true => 0x9C00,
             false => 0x9800,
         };
 
-        self.wy = self.mmu.borrow().ppu.wy;
-        self.wx = self.mmu.borrow().ppu.wx;
+        self.wy = mmu.wy;
+        self.wx = mmu.wx;
         let bg_map_x = (self.scan_x as u16 + 7 - self.wx as u16) % 256 / 8;
         let bg_map_y = self.window_internal_line_index as u16 / 8;
         let bg_map_index = bg_map_x + bg_map_y * 32;
-        let bg_map_byte = self
-            .mmu
-            .borrow()
-            .ppu
-            .vram
-            .get_by_bank(window_map_start + bg_map_index, false);
+        let bg_map_byte = mmu.vram.get_by_bank(window_map_start + bg_map_index, false);
 
         let tile_index: u16 = if bg_window_tile_data_area {
             0x8000 + bg_map_byte as u16 * 8 * 2
@@ -374,70 +422,55 @@ impl Fetcher for FetcherWindow {
             (0x9000 as i32 + (bg_map_byte as i8) as i32 * 8 * 2) as u16
         };
         if self.mode == GameBoyMode::GBC {
-            let bg_map_attr_val = self
-                .mmu
-                .borrow()
-                .ppu
-                .vram
-                .get_by_bank(window_map_start + bg_map_index, true);
+            let bg_map_attr_val = mmu.vram.get_by_bank(window_map_start + bg_map_index, true);
             self.bg_map_attr = BGMapAttr::from(bg_map_attr_val);
         }
         tile_index
     }
-    fn get_tile_data_low(&self) -> u8 {
+    fn get_tile_data_low(&self, mmu: &PpuMmu) -> u8 {
         let tile_index = self.tile_index;
         let mut tile_pixel_y = self.window_internal_line_index as u16 % 8;
         if self.mode == GameBoyMode::GBC {
             if self.bg_map_attr.y_flip {
                 tile_pixel_y = (8 - 1) - tile_pixel_y;
             }
-            self.mmu
-                .borrow()
-                .ppu
-                .vram
+            mmu.vram
                 .get_by_bank(tile_index + tile_pixel_y * 2, self.bg_map_attr.vram_bank)
         } else {
-            self.mmu
-                .borrow()
-                .ppu
-                .vram
-                .get_by_bank(tile_index + tile_pixel_y * 2, false)
+            mmu.vram.get_by_bank(tile_index + tile_pixel_y * 2, false)
         }
     }
-    fn get_tile_data_high(&self) -> u8 {
+    fn get_tile_data_high(&self, mmu: &PpuMmu) -> u8 {
         let tile_index = self.tile_index;
         let mut tile_pixel_y = self.window_internal_line_index as u16 % 8;
         if self.mode == GameBoyMode::GBC {
             if self.bg_map_attr.y_flip {
                 tile_pixel_y = (8 - 1) - tile_pixel_y;
             }
-            self.mmu.borrow().ppu.vram.get_by_bank(
+            mmu.vram.get_by_bank(
                 tile_index + tile_pixel_y * 2 + 1,
                 self.bg_map_attr.vram_bank,
             )
         } else {
-            self.mmu
-                .borrow()
-                .ppu
-                .vram
+            mmu.vram
                 .get_by_bank(tile_index + tile_pixel_y * 2 + 1, false)
         }
     }
-    fn get_buffer(&mut self) -> Vec<Pixel> {
+    fn get_buffer(&mut self, mmu: &PpuMmu) -> Vec<Pixel> {
         let mut result = Vec::new();
         let mut get_pixel_bit: Box<dyn Fn(u8) -> u8> = Box::new(|index: u8| 8 - index - 1);
         if (self.mode == GameBoyMode::GBC) && self.bg_map_attr.x_flip {
             get_pixel_bit = Box::new(|index: u8| index);
         }
-        let bg_window_enable = self.mmu.borrow().ppu.lcdc.bg_window_enable;
+        let bg_window_enable = mmu.lcdc.bg_window_enable;
         let buffer_index_start = (self.scan_x as u16 + 7 - self.wx as u16) % 8;
         for buffer_index in buffer_index_start..8 {
             let pixel_bit = get_pixel_bit(buffer_index as u8);
             let pixel_low = check_bit(self.tile_data_low, pixel_bit as u8);
             let pixel_high = check_bit(self.tile_data_high, pixel_bit as u8);
             let pvalue = (pixel_low as u8) | ((pixel_high as u8) << 1);
-            let pcolor = self.get_color_index(pvalue);
-            let pixel = if self.mode == GameBoyMode::GB {
+            let pcolor = self.get_color_index(mmu, pvalue);
+            let pixel = if self.mode != GameBoyMode::GBC {
                 if !bg_window_enable {
                     Pixel {
                         ptype: Window,
@@ -466,12 +499,12 @@ impl Fetcher for FetcherWindow {
         }
         result
     }
-    fn get_color_index(&self, pvalue: u8) -> u8 {
+    fn get_color_index(&self, mmu: &PpuMmu, pvalue: u8) -> u8 {
         if self.mode == GameBoyMode::GBC {
             let bg_palette = self.bg_map_attr.bg_palette;
             bg_palette * 4 * 2 + pvalue * 2
         } else {
-            let palette = self.mmu.borrow().ppu.bgp;
+            let palette = mmu.bgp;
             match pvalue {
                 0 => palette & 0b11,
                 1 => (palette & 0b1100) >> 2,
@@ -494,7 +527,6 @@ struct FetcherSprite {
     scan_y: u8,
     oam: OAM,
     cycles: u16,
-    mmu: Rc<RefCell<Mmu>>,
     status: FetcherStatus,
     tile_index: u16,
     tile_data_low: u8,
@@ -507,119 +539,124 @@ impl FetcherSprite {
     }
 }
 impl Fetcher for FetcherSprite {
-    fn new(mmu: Rc<RefCell<Mmu>>, scan_x: u8, scan_y: u8) -> Self {
-        let mode = mmu.borrow().mode;
+    fn new(mode: GameBoyMode, scan_x: u8, scan_y: u8) -> Self {
         Self {
             mode,
             scan_x,
             scan_y,
             oam: OAM::default(),
-            mmu,
             cycles: 0,
-            status: GetTile,
+            status: Tile,
             tile_index: 0,
             tile_data_low: 0,
             tile_data_high: 0,
             buffer: Vec::with_capacity(8),
         }
     }
-    fn trick(&mut self) {
+    fn trick(&mut self, mmu: &PpuMmu) {
         if self.cycles == 1 {
             self.cycles = 0;
             return;
         }
         self.cycles += 1;
         match self.status {
-            GetTile => {
-                self.tile_index = self.get_tile();
-                self.status = GetTileDataLow;
+            Tile => {
+                self.tile_index = self.get_tile(mmu);
+                self.status = DataLow;
             }
-            GetTileDataLow => {
-                self.tile_data_low = self.get_tile_data_low();
-                self.status = GetTileDataHigh;
+            DataLow => {
+                self.tile_data_low = self.get_tile_data_low(mmu);
+                self.status = DataHigh;
             }
-            GetTileDataHigh => {
-                self.tile_data_high = self.get_tile_data_high();
-                self.buffer = self.get_buffer();
-                self.status = GetTile;
+            DataHigh => {
+                self.tile_data_high = self.get_tile_data_high(mmu);
+                self.buffer = self.get_buffer(mmu);
+                self.status = Tile;
             }
         }
     }
-    fn get_tile(&mut self) -> u16 {
-        if self.mmu.borrow().ppu.lcdc.obj_size {
+    fn get_tile(&mut self, mmu: &PpuMmu) -> u16 {
+        if mmu.lcdc.obj_size {
             0x8000 + (self.oam.tile_index as u16 & 0xFE) * 16
         } else {
             0x8000 + (self.oam.tile_index as u16) * 16
         }
     }
-    fn get_tile_data_low(&self) -> u8 {
+    fn get_tile_data_low(&self, mmu: &PpuMmu) -> u8 {
         let tile_index = self.tile_index;
-        let obj_size = self.mmu.borrow().ppu.lcdc.obj_size;
+        let obj_size = mmu.lcdc.obj_size;
         let height = if obj_size { 16 } else { 8 };
         let mut tile_pixel_y = (self.scan_y as u16 + 16 - self.oam.y as u16) % height;
         if self.oam.y_flip {
             tile_pixel_y = (height - 1) - tile_pixel_y;
         }
-        self.mmu.borrow().ppu.vram.get_by_bank(
+        mmu.vram.get_by_bank(
             tile_index + tile_pixel_y * 2,
-            if self.mode == GameBoyMode::GB {
+            if self.mode != GameBoyMode::GBC {
                 false
             } else {
                 self.oam.vram_bank
             },
         )
     }
-    fn get_tile_data_high(&self) -> u8 {
+    fn get_tile_data_high(&self, mmu: &PpuMmu) -> u8 {
         let tile_index = self.tile_index;
-        let obj_size = self.mmu.borrow().ppu.lcdc.obj_size;
+        let obj_size = mmu.lcdc.obj_size;
         let height = if obj_size { 16 } else { 8 };
         let mut tile_pixel_y = (self.scan_y as u16 + 16 - self.oam.y as u16) % height;
         if self.oam.y_flip {
             tile_pixel_y = (height - 1) - tile_pixel_y;
         }
-        self.mmu.borrow().ppu.vram.get_by_bank(
+        mmu.vram.get_by_bank(
             tile_index + tile_pixel_y * 2 + 1,
-            if self.mode == GameBoyMode::GB {
+            if self.mode != GameBoyMode::GBC {
                 false
             } else {
                 self.oam.vram_bank
             },
         )
     }
-    fn get_buffer(&mut self) -> Vec<Pixel> {
+    fn get_buffer(&mut self, mmu: &PpuMmu) -> Vec<Pixel> {
         let mut result = Vec::new();
         let mut get_pixel_bit: Box<dyn Fn(u8) -> u8> = Box::new(|index: u8| 8 - index - 1);
         if self.oam.x_flip {
             get_pixel_bit = Box::new(|index: u8| index);
         }
         let buffer_index_start = (self.scan_x as u16 + 8 - self.oam.x as u16) % 8;
+        // with OPRI set the object more to the left wins, the OAM index only breaks ties
+        let oam_priority = if mmu.opri & 1 != 0 {
+            self.oam.x as usize * 40 + self.oam.priority
+        } else {
+            self.oam.priority
+        };
         for buffer_index in buffer_index_start..8 {
             let pixel_bit = get_pixel_bit(buffer_index as u8);
             let pixel_low = check_bit(self.tile_data_low, pixel_bit as u8);
             let pixel_high = check_bit(self.tile_data_high, pixel_bit as u8);
             let pvalue = (pixel_low as u8) | ((pixel_high as u8) << 1);
-            let pcolor = self.get_color_index(pvalue);
+            let pcolor = self.get_color_index(mmu, pvalue);
             result.push(Pixel {
                 ptype: Sprite,
                 pvalue,
                 pcolor,
                 bg_window_over_obj: self.oam.bg_window_over_obj,
-                oam_priority: self.oam.priority,
+                oam_priority,
+                obj1: self.oam.palette,
                 ..Pixel::default()
             });
         }
         result
     }
-    fn get_color_index(&self, pvalue: u8) -> u8 {
+    fn get_color_index(&self, mmu: &PpuMmu, pvalue: u8) -> u8 {
         if self.mode == GameBoyMode::GBC {
             let cpalette = self.oam.cpalette;
             cpalette * 4 * 2 + pvalue * 2
         } else {
             let palette = {
                 if self.oam.palette {
-                    self.mmu.borrow().ppu.op1
+                    mmu.op1
                 } else {
-                    self.mmu.borrow().ppu.op0
+                    mmu.op0
                 }
             };
             match pvalue {
@@ -661,7 +698,7 @@ impl OAM {
         let height = if obj_size { 16 } else { 8 };
         let y_start = self.y as i32 - 16;
         let y_end = self.y as i32 + height - 16;
-        ((ly as i32) >= y_start) && ((ly as i32) < y_end) && (self.x != 0)
+        ((ly as i32) >= y_start) && ((ly as i32) < y_end)
     }
 }
 impl From<u8> for OAM {
@@ -710,22 +747,20 @@ struct FIFO {
     y: u8,
     window_internal_line_counters: u8,
     status: FifoTrick,
-    mmu: Rc<RefCell<Mmu>>,
     fetcher: Box<dyn Fetcher>,
     sprite_queue: VecDeque<Pixel>,
     queue: VecDeque<Pixel>,
     oam: Vec<OAM>,
 }
 impl FIFO {
-    fn new(mmu: Rc<RefCell<Mmu>>) -> Self {
+    fn new(mode: GameBoyMode) -> Self {
         let x = 0;
         let y = 0;
-        let fetcher = Box::new(FetcherBg::new(mmu.clone(), x, y));
+        let fetcher = Box::new(FetcherBg::new(mode, x, y));
         Self {
             x,
             y,
             window_internal_line_counters: 0,
-            mmu,
             status: FifoTrick::BgWindow,
             fetcher,
             sprite_queue: VecDeque::new(),
@@ -733,46 +768,50 @@ impl FIFO {
             oam: vec![],
         }
     }
-    fn init(&mut self, y: u8) {
+    fn init(&mut self, mmu: &PpuMmu, y: u8) {
         self.x = 0;
         self.y = y;
         if y == 0 {
             self.window_internal_line_counters = 0;
         }
-        if self.check_window(160) {
+        if self.check_window(mmu, 160) {
             self.window_internal_line_counters = self.window_internal_line_counters + 1;
         }
         self.sprite_queue.clear();
         self.queue.clear();
         self.oam.clear();
         self.status = FifoTrick::BgWindow;
-        self.fetcher = self.get_fetcher_window_or_bg(self.check_window_or_bg(self.x), self.x, y);
+        self.fetcher =
+            self.get_fetcher_window_or_bg(mmu, self.check_window_or_bg(mmu, self.x), self.x, y);
     }
     fn set_oam(&mut self, oam: Vec<OAM>) {
         self.oam = oam;
     }
-    fn trick(&mut self) -> Option<Pixel> {
+    fn trick(&mut self, mmu: &PpuMmu) -> Option<Pixel> {
         match self.status {
             FifoTrick::BgWindow => {
                 let mut result = None;
                 if self.queue.len() > 8 {
                     // 检查当前像素是否上层有Window或Sprite
                     let front = self.front().unwrap().to_owned();
-                    let new_fetch_event = self.check_overlap(front.ptype, self.x);
+                    let new_fetch_event = self.check_overlap(mmu, front.ptype, self.x);
                     if let Some(event) = new_fetch_event {
                         match event {
                             Window => {
                                 self.status = FifoTrick::BgWindow;
                                 self.queue.clear();
                                 self.fetcher =
-                                    self.get_fetcher_window_or_bg(Window, self.x, self.y);
+                                    self.get_fetcher_window_or_bg(mmu, Window, self.x, self.y);
                                 return None;
                             }
                             Sprite => {
                                 self.status = FifoTrick::Sprite;
                                 let oam = self.oam_pop(self.x).unwrap();
-                                let mut fetcher =
-                                    Box::new(FetcherSprite::new(self.mmu.clone(), self.x, self.y));
+                                let mut fetcher = Box::new(FetcherSprite::new(
+                                    mmu.gameboy_mode(),
+                                    self.x,
+                                    self.y,
+                                ));
                                 fetcher.set_oam(oam);
                                 self.fetcher = fetcher;
                                 return None;
@@ -784,7 +823,7 @@ impl FIFO {
                     }
                     // 执行到这，无异常，正常压入弹出流程
                     self.x += 1;
-                    result = self.pop_front();
+                    result = self.pop_front(mmu);
                 }
                 if self.fetcher.buffer().len() > 0 {
                     if self.queue.len() <= 8 {
@@ -795,13 +834,14 @@ impl FIFO {
                         let fetcher_x = self.x + self.queue.len() as u8;
                         self.status = FifoTrick::BgWindow;
                         self.fetcher = self.get_fetcher_window_or_bg(
-                            self.check_window_or_bg(fetcher_x),
+                            mmu,
+                            self.check_window_or_bg(mmu, fetcher_x),
                             fetcher_x,
                             self.y,
                         );
                     }
                 } else {
-                    self.fetcher.trick();
+                    self.fetcher.trick(mmu);
                 }
                 result
             }
@@ -828,37 +868,38 @@ impl FIFO {
                     self.status = FifoTrick::BgWindow;
                     let fetcher_x = self.x + self.queue.len() as u8;
                     self.fetcher = self.get_fetcher_window_or_bg(
-                        self.check_window_or_bg(fetcher_x),
+                        mmu,
+                        self.check_window_or_bg(mmu, fetcher_x),
                         fetcher_x,
                         self.y,
                     );
                 } else {
-                    self.fetcher.trick();
+                    self.fetcher.trick(mmu);
                 }
                 None
             }
         }
     }
-    fn check_overlap(&self, ptype: PixelType, x: u8) -> Option<PixelType> {
+    fn check_overlap(&self, mmu: &PpuMmu, ptype: PixelType, x: u8) -> Option<PixelType> {
         match ptype {
             BG => {
-                if self.check_window(x) {
+                if self.check_window(mmu, x) {
                     Some(Window)
-                } else if self.check_sprite(x) {
+                } else if self.check_sprite(mmu, x) {
                     Some(Sprite)
                 } else {
                     None
                 }
             }
             Window => {
-                if self.check_sprite(x) {
+                if self.check_sprite(mmu, x) {
                     Some(Sprite)
                 } else {
                     None
                 }
             }
             Sprite => {
-                if self.check_sprite(x) {
+                if self.check_sprite(mmu, x) {
                     Some(Sprite)
                 } else {
                     None
@@ -866,17 +907,17 @@ impl FIFO {
             }
         }
     }
-    fn check_window(&self, x: u8) -> bool {
-        let window_enable = self.mmu.borrow().ppu.lcdc.window_enable;
+    fn check_window(&self, mmu: &PpuMmu, x: u8) -> bool {
+        let window_enable = mmu.lcdc.window_enable;
         if !window_enable {
             return false;
         }
-        let wy = self.mmu.borrow().ppu.wy;
-        let wx = self.mmu.borrow().ppu.wx;
+        let wy = mmu.wy;
+        let wx = mmu.wx;
         (x + 7 >= wx) && (self.y >= wy)
     }
-    fn check_sprite(&self, x: u8) -> bool {
-        let obj_enable = self.mmu.borrow().ppu.lcdc.obj_enable;
+    fn check_sprite(&self, mmu: &PpuMmu, x: u8) -> bool {
+        let obj_enable = mmu.lcdc.obj_enable;
         if !obj_enable {
             return false;
         }
@@ -895,27 +936,32 @@ impl FIFO {
                 break;
             }
         }
-        if !oam_index.is_none() {
-            let index = oam_index.unwrap();
+        if let Some(index) = oam_index {
             Some(self.oam.remove(index))
         } else {
             None
         }
     }
-    fn get_fetcher_window_or_bg(&self, ptype: PixelType, x: u8, y: u8) -> Box<dyn Fetcher> {
-        let mmu = self.mmu.clone();
+    fn get_fetcher_window_or_bg(
+        &self,
+        mmu: &PpuMmu,
+        ptype: PixelType,
+        x: u8,
+        y: u8,
+    ) -> Box<dyn Fetcher> {
+        let mode = mmu.gameboy_mode();
         match ptype {
-            BG => Box::new(FetcherBg::new(mmu, x, y)),
+            BG => Box::new(FetcherBg::new(mode, x, y)),
             Window => {
-                let mut fetcher = FetcherWindow::new(mmu, x, y);
+                let mut fetcher = FetcherWindow::new(mode, x, y);
                 fetcher.set_window_internal_line_index(self.window_internal_line_counters - 1);
                 Box::new(fetcher)
             }
             _ => panic!(""),
         }
     }
-    fn check_window_or_bg(&self, x: u8) -> PixelType {
-        if self.check_window(x) {
+    fn check_window_or_bg(&self, mmu: &PpuMmu, x: u8) -> PixelType {
+        if self.check_window(mmu, x) {
             Window
         } else {
             BG
@@ -927,13 +973,13 @@ impl FIFO {
     fn push_back(&mut self, pixel: Pixel) {
         self.queue.push_back(pixel);
     }
-    fn pop_front(&mut self) -> Option<Pixel> {
+    fn pop_front(&mut self, mmu: &PpuMmu) -> Option<Pixel> {
         let sprite_pixel_option = self.sprite_queue.pop_front();
         match sprite_pixel_option {
             Some(sprite_pixel) => {
                 let bg_pixel = self.queue.pop_front().unwrap();
-                if self.mmu.borrow().mode == GameBoyMode::GBC {
-                    let bg_window_enable = self.mmu.borrow().ppu.lcdc.bg_window_enable;
+                if mmu.gameboy_mode() == GameBoyMode::GBC {
+                    let bg_window_enable = mmu.lcdc.bg_window_enable;
                     if !bg_window_enable {
                         if sprite_pixel.pvalue == 0 {
                             Some(bg_pixel)
@@ -992,164 +1038,263 @@ pub enum PpuStatus {
     VBlank = 1,
 }
 pub struct PPU {
-    mode: GameBoyMode,
+    // the dot in the line
     cycles: u32,
+    line: u8,
+    drawing_end: u32,
+    // the dot every pixel of the line comes out of the fifo at
+    pixel_dots: Vec<u32>,
+    lcd_on_line: bool,
     fifo: FIFO,
-    pub mmu: Rc<RefCell<Mmu>>,
+    mode: GameBoyMode,
     ly_buffer: Vec<u32>,
     lcd_enable: bool,
-    pub frame_buffer: [u32; WIDTH * HEIGHT],
+    // on the heap, a gameboy is moved around by value
+    pub frame_buffer: Box<[u32; WIDTH * HEIGHT]>,
     init_color: u32,
+    palette: DmgPalette,
 }
 impl PPU {
-    pub fn new(mmu: Rc<RefCell<Mmu>>) -> Self {
-        let mode = mmu.borrow().mode;
-        let fifo = FIFO::new(mmu.clone());
-        let init_color = if mode == GameBoyMode::GBC {
-            0xFFFFFF
+    pub fn new(mode: GameBoyMode) -> Self {
+        let fifo = FIFO::new(mode);
+        let palette = DmgPalette::default();
+        let init_color = if mode == GameBoyMode::GB {
+            palette.bg[0]
         } else {
-            Color::WHITE as u32
+            0xFFFFFF
         };
         Self {
-            mode,
             cycles: 0,
-            mmu,
+            line: 0,
+            drawing_end: 0,
+            pixel_dots: Vec::with_capacity(WIDTH),
+            lcd_on_line: false,
             fifo,
+            mode,
             lcd_enable: true,
             ly_buffer: Vec::with_capacity(WIDTH),
-            frame_buffer: [init_color as u32; WIDTH * HEIGHT],
+            frame_buffer: Box::new([init_color as u32; WIDTH * HEIGHT]),
             init_color,
+            palette,
         }
     }
-    pub fn trick(&mut self) -> bool {
-        let lcd_enable = self.mmu.borrow().ppu.lcdc.lcd_ppu_enable;
+    // one dot, the mode follows from the line and the dot in it, no object is found while
+    // OAM DMA holds the bus
+    pub fn trick(&mut self, mmu: &mut PpuMmu, oam_dma: bool) -> bool {
+        let lcd_enable = mmu.lcdc.lcd_ppu_enable;
         let mut is_refresh = false;
         if !lcd_enable {
             if self.lcd_enable == lcd_enable {
                 return is_refresh;
             }
             self.cycles = 0;
+            self.line = 0;
             self.ly_buffer = Vec::with_capacity(WIDTH);
-            self.frame_buffer = [self.init_color; WIDTH * HEIGHT];
-            self.fifo = FIFO::new(self.mmu.clone());
-            self.mmu.borrow_mut().ppu.ly = 0;
-            self.mmu.borrow_mut().ppu.stat.mode_flag = HBlank;
+            self.frame_buffer.fill(self.init_color);
+            self.fifo = FIFO::new(mmu.gameboy_mode());
+            mmu.ly = 0;
+            self.set_mode(mmu, HBlank);
+            mmu.set_mode_interrupt();
             is_refresh = true;
         } else {
-            let mode_flag = self.mmu.borrow().ppu.stat.mode_flag;
-            match mode_flag {
-                OAMScan => {
-                    if self.cycles == 0 {
-                        self.set_ly_interrupt();
-                        self.set_mode_interrupt();
-                        let ly = self.get_ly();
-                        self.fifo.init(ly);
-                        let oams = self.oam_scan();
-                        self.fifo.set_oam(oams);
-                    }
-                    if self.cycles == 79 {
-                        self.set_mode(Drawing);
+            // the first line after the LCD is turned on has no OAM scan, and no HBlank interrupt
+            // before its drawing
+            if !self.lcd_enable {
+                self.lcd_on_line = true;
+                self.cycles = LCD_ON_DOTS;
+                self.fifo.init(mmu, 0);
+                let oams = self.oam_scan(mmu, 0, oam_dma);
+                self.fifo.set_oam(oams);
+                mmu.set_mode_interrupt();
+            }
+            let line = self.line;
+            let dot = self.cycles;
+            if (line as usize) < HEIGHT {
+                if dot == 0 {
+                    if !self.lcd_on_line && line != 0 {
+                        mmu.set_oam_interrupt(true);
                     }
-                    self.cycles += 1;
+                    self.fifo.init(mmu, line);
+                    let oams = self.oam_scan(mmu, line, oam_dma);
+                    self.fifo.set_oam(oams);
                 }
-                Drawing => {
-                    let pixel_option = self.fifo.trick();
-                    if let Some(pixel) = pixel_option {
-                        self.ly_buffer.push(self.get_pixel_color(pixel));
-                        if self.ly_buffer.len() == WIDTH {
-                            let ly = self.get_ly();
-                            for (scan_x, pixel) in self.ly_buffer.iter().enumerate() {
-                                self.frame_buffer[(ly as usize * WIDTH + scan_x) as usize] = *pixel;
-                            }
-                            self.set_mode(HBlank);
-                            self.set_mode_interrupt();
-                        }
-                    } else {
-                    }
-                    self.cycles += 1;
+                let scan_delay = if line == 0 {
+                    LINE0_DELAY
+                } else {
+                    OAM_SCAN_DELAY
+                };
+                if dot == scan_delay && !self.lcd_on_line {
+                    self.set_mode(mmu, OAMScan);
+                    mmu.set_oam_interrupt(true);
                 }
-                HBlank => {
-                    let ly = self.get_ly();
-                    if self.cycles >= 455 {
-                        if ly == 143 {
-                            self.set_mode(VBlank);
-                        } else {
-                            self.set_mode(OAMScan);
-                        }
-                        self.set_ly(ly + 1);
-                        self.cycles = 0;
-                    } else {
-                        self.cycles += 1;
-                    }
+                if dot == OAM_SCAN_DELAY + OAM_SCAN_DOTS {
+                    self.lcd_on_line = false;
+                    self.set_mode(mmu, Drawing);
+                    mmu.set_oam_interrupt(false);
+                    self.pixel_dots = self.pixel_dots(mmu, dot);
+                    // the drawing ends with the last pixel
+                    self.drawing_end = LINE_DOTS;
                 }
-                VBlank => {
-                    let ly = self.get_ly();
-                    if self.cycles == 0 {
-                        self.set_ly_interrupt();
-                        if ly == 144 {
-                            is_refresh = true;
-                            self.set_mode_interrupt();
-                        }
+                if dot >= OAM_SCAN_DELAY + OAM_SCAN_DOTS && dot < self.drawing_end {
+                    // a pixel comes out at the dot it does on hardware, registers written in
+                    // the middle of the line change the pixels after it
+                    while self.ly_buffer.len() < WIDTH
+                        && self.pixel_dots[self.ly_buffer.len()] <= dot
+                    {
+                        self.draw(mmu);
                     }
-                    let ly = self.get_ly();
-                    if self.cycles == 455 {
-                        if ly == 153 {
-                            self.set_mode(OAMScan);
-                            self.set_ly(0);
-                        } else {
-                            self.set_ly(ly + 1);
+                    if self.ly_buffer.len() == WIDTH {
+                        for (scan_x, pixel) in self.ly_buffer.iter().enumerate() {
+                            self.frame_buffer[line as usize * WIDTH + scan_x] = *pixel;
                         }
-                        self.cycles = 0;
-                    } else {
-                        self.cycles += 1;
+                        self.set_mode(mmu, HBlank);
+                        self.drawing_end = dot + 1;
                     }
                 }
+                if dot + 1 == self.drawing_end + HBLANK_INTERRUPT_DELAY {
+                    mmu.set_hblank_interrupt();
+                }
+            } else if line == HEIGHT as u8 && dot == 0 {
+                self.set_mode(mmu, VBlank);
+                mmu.interrupt_flag_vblank = true;
+                // the OAM source goes up at the start of VBlank too
+                mmu.set_oam_interrupt(true);
+                is_refresh = true;
+            } else if line == HEIGHT as u8 && dot == OAM_SCAN_DELAY {
+                mmu.set_oam_interrupt(false);
+            } else if line == 153 && dot == LY_153_DOTS {
+                // LY is back to 0 for most of the last line
+                mmu.set_ly(0);
+            }
+            // LY only moves on a little into the line
+            if dot == LY_DELAY_DOTS {
+                mmu.set_ly(line);
+            }
+            self.cycles += 1;
+            if self.cycles == LINE_DOTS {
+                self.cycles = 0;
+                self.line = (line + 1) % LINES;
             }
         }
         self.lcd_enable = lcd_enable;
         is_refresh
     }
-    fn get_pixel_color(&self, pixel: Pixel) -> u32 {
-        if self.mode == GameBoyMode::GBC {
-            let rgb_memory = match pixel.ptype {
-                BG | Window => self.mmu.borrow().ppu.bcp.memory,
-                Sprite => self.mmu.borrow().ppu.ocp.memory,
-            };
-            let rgb_low = rgb_memory[pixel.pcolor as usize];
-            let rgb_high = rgb_memory[pixel.pcolor as usize + 1];
-            let color = u16::from_be_bytes([rgb_high, rgb_low]);
-            let blue = ((color & 0x7C00) >> 10) as u32;
-            let green = ((color & 0x03E0) >> 5) as u32;
-            let red = (color & 0x001F) as u32;
-
-            let hex_red = (red << 3) | (red >> 2);
-            let hex_green = (green << 3) | (green >> 2);
-            let hex_blue = (blue << 3) | (blue >> 2);
-
-            let result = (hex_red << 16) | (hex_green << 8) | hex_blue;
-            result
-        } else {
-            match pixel.pcolor {
-                0 => Color::WHITE as u32,
-                1 => Color::LightGray as u32,
-                2 => Color::DarkGray as u32,
-                3 => Color::BlackGray as u32,
-                _ => {
-                    panic!("color_value is out of range {}", pixel.pcolor);
+    // the fifo runs until the next pixel is out
+    fn draw(&mut self, mmu: &PpuMmu) {
+        loop {
+            if let Some(pixel) = self.fifo.trick(mmu) {
+                self.ly_buffer.push(self.get_pixel_color(mmu, pixel));
+                return;
+            }
+        }
+    }
+    // one pixel a dot after the first two tile fetches, held back by the fine scroll at the
+    // start, by the window where it starts and by every object on the line where it is
+    fn pixel_dots(&self, mmu: &PpuMmu, start: u32) -> Vec<u32> {
+        let scx = mmu.scx as u32;
+        let mut stalls = vec![(0, scx & 0x07)];
+        if self.fifo.check_window(mmu, WIDTH as u8 - 1) {
+            stalls.push(((mmu.wx as u32).saturating_sub(7), 6));
+        }
+        if mmu.lcdc.obj_enable {
+            let mut xs: Vec<u32> = self
+                .fifo
+                .oam
+                .iter()
+                .map(|oam| oam.x as u32)
+                .filter(|&x| x < WIDTH as u32 + 8)
+                .collect();
+            xs.sort_unstable();
+            let mut fetched_tile = None;
+            for x in xs {
+                let mut dots = 6;
+                // an object waits for the rest of the background tile under it, once per tile
+                let tile = (x + scx) / 8;
+                if fetched_tile != Some(tile) {
+                    dots += 5u32.saturating_sub((x + scx) % 8);
+                    fetched_tile = Some(tile);
                 }
+                stalls.push((x.saturating_sub(8), dots));
             }
         }
+        let mut dot = start + DRAWING_DOTS - WIDTH as u32;
+        (0..WIDTH as u32)
+            .map(|x| {
+                dot += stalls
+                    .iter()
+                    .filter(|(stall_x, _)| *stall_x == x)
+                    .map(|(_, dots)| dots)
+                    .sum::<u32>();
+                dot + x
+            })
+            .collect()
+    }
+    pub fn palette(&self) -> DmgPalette {
+        self.palette
+    }
+    // the next line drawn on DMG uses it
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.palette = palette;
+        if self.mode == GameBoyMode::GB {
+            self.init_color = palette.bg[0];
+        }
+    }
+    fn get_pixel_color(&self, mmu: &PpuMmu, pixel: Pixel) -> u32 {
+        let index = match mmu.gameboy_mode() {
+            GameBoyMode::GB => {
+                let shades = match pixel.ptype {
+                    BG | Window => self.palette.bg,
+                    Sprite if pixel.obj1 => self.palette.obj1,
+                    Sprite => self.palette.obj0,
+                };
+                return shades[Self::dmg_shade(mmu, pixel)];
+            }
+            GameBoyMode::GBC => pixel.pcolor as usize,
+            // the shades of BGP and OBP0/OBP1 pick from the first palettes the boot rom left
+            GameBoyMode::GBCCompat => match pixel.ptype {
+                Sprite if pixel.obj1 => 8 + Self::dmg_shade(mmu, pixel) * 2,
+                _ => Self::dmg_shade(mmu, pixel) * 2,
+            },
+        };
+        let rgb_memory = match pixel.ptype {
+            BG | Window => mmu.bcp.memory,
+            Sprite => mmu.ocp.memory,
+        };
+        let rgb_low = rgb_memory[index];
+        let rgb_high = rgb_memory[index + 1];
+        let color = u16::from_be_bytes([rgb_high, rgb_low]);
+        let blue = ((color & 0x7C00) >> 10) as u32;
+        let green = ((color & 0x03E0) >> 5) as u32;
+        let red = (color & 0x001F) as u32;
+
+        let hex_red = (red << 3) | (red >> 2);
+        let hex_green = (green << 3) | (green >> 2);
+        let hex_blue = (blue << 3) | (blue >> 2);
+
+        (hex_red << 16) | (hex_green << 8) | hex_blue
+    }
+    // BGP and OBP0/OBP1 as the pixel comes out, not as it was fetched
+    fn dmg_shade(mmu: &PpuMmu, pixel: Pixel) -> usize {
+        let palette = match pixel.ptype {
+            BG | Window if !mmu.lcdc.bg_window_enable => return 0,
+            BG | Window => mmu.bgp,
+            Sprite if pixel.obj1 => mmu.op1,
+            Sprite => mmu.op0,
+        };
+        (palette >> (pixel.pvalue * 2) & 0x03) as usize
     }
-    fn oam_scan(&self) -> Vec<OAM> {
-        let ly = self.get_ly();
+    fn oam_scan(&self, mmu: &PpuMmu, ly: u8, oam_dma: bool) -> Vec<OAM> {
         let mut result = Vec::with_capacity(10);
-        let obj_size = self.mmu.borrow().ppu.lcdc.obj_size;
+        let obj_size = mmu.lcdc.obj_size;
+        if oam_dma {
+            return result;
+        }
         for index in 00..40 {
             let oam_address = 0xFE00 + (index as u16) * 4;
-            let y = self.mmu.borrow().get(oam_address);
-            let x = self.mmu.borrow().get(oam_address + 1);
-            let tile_index = self.mmu.borrow().get(oam_address + 2);
-            let flags = self.mmu.borrow().get(oam_address + 3);
+            let y = mmu.get(oam_address);
+            let x = mmu.get(oam_address + 1);
+            let tile_index = mmu.get(oam_address + 2);
+            let flags = mmu.get(oam_address + 3);
             let mut oam = OAM::from(flags);
             oam.set(y, x, tile_index, index);
             if oam.is_scaned(ly, obj_size) {
@@ -1161,16 +1306,7 @@ impl PPU {
         }
         result
     }
-    fn set_ly(&mut self, ly: u8) {
-        self.mmu.borrow_mut().ppu.ly = ly;
-    }
-    fn set_ly_interrupt(&mut self) {
-        self.mmu.borrow_mut().ppu.set_ly_interrupt();
-    }
-    fn get_ly(&self) -> u8 {
-        self.mmu.borrow().ppu.ly
-    }
-    fn set_mode(&mut self, mode: PpuStatus) {
+    fn set_mode(&mut self, mmu: &mut PpuMmu, mode: PpuStatus) {
         match mode {
             OAMScan => {
                 self.ly_buffer = Vec::with_capacity(WIDTH);
@@ -1181,33 +1317,12 @@ impl PPU {
             }
             VBlank => {}
         };
-        self.mmu.borrow_mut().ppu.set_mode(mode);
-    }
-    fn set_mode_interrupt(&mut self) {
-        self.mmu.borrow_mut().ppu.set_mode_interrupt();
+        mmu.set_mode(mode);
     }
 }
 impl Default for PPU {
     fn default() -> Self {
-        let mmu: Mmu = Default::default();
-        let mmu = Rc::new(RefCell::new(mmu));
-        let mode = mmu.borrow().mode;
-        let fifo = FIFO::new(mmu.clone());
-        let init_color = if mode == GameBoyMode::GBC {
-            0xFFFFFF
-        } else {
-            Color::WHITE as u32
-        };
-        Self {
-            mode,
-            cycles: 0,
-            mmu,
-            fifo,
-            lcd_enable: true,
-            ly_buffer: Vec::with_capacity(WIDTH),
-            frame_buffer: [init_color as u32; WIDTH * HEIGHT],
-            init_color,
-        }
+        Self::new(GameBoyMode::GB)
     }
 }
 
@@ -1504,6 +1619,8 @@ pub struct PpuMmu {
     pub bgp: u8,
     pub op0: u8,
     pub op1: u8,
+    // bit 0 set gives objects the DMG priority
+    opri: u8,
     vram: VRAM,
     bcp: BCP,
     ocp: OCP,
@@ -1511,6 +1628,13 @@ pub struct PpuMmu {
     oam: [u8; 0xFE9F - 0xFE00 + 1],
     pub interrupt_flag_lcdstat: bool,
     pub interrupt_flag_vblank: bool,
+    // the source of the OAM scan interrupt, it goes up ahead of STAT
+    oam_interrupt: bool,
+    // and the HBlank one comes up after STAT
+    hblank_interrupt: bool,
+    // LY == LYC as the LCD last saw it, it holds while the LCD is off
+    lyc_flag: bool,
+    stat_line: bool,
 }
 impl PpuMmu {
     pub fn new(mode: GameBoyMode) -> Self {
@@ -1531,50 +1655,107 @@ impl PpuMmu {
             bgp: 0,
             op0: 0,
             op1: 0,
+            opri: 0,
             vram,
             bcp,
             ocp,
             oam: [0; 0xFE9F - 0xFE00 + 1],
             interrupt_flag_lcdstat: false,
             interrupt_flag_vblank: false,
+            oam_interrupt: false,
+            hblank_interrupt: false,
+            lyc_flag: false,
+            stat_line: false,
         }
     }
     pub fn set_mode(&mut self, mode: PpuStatus) {
         self.stat.mode_flag = mode;
+        self.hblank_interrupt = false;
+    }
+    pub fn set_gameboy_mode(&mut self, mode: GameBoyMode) {
+        self.vram.mode = mode;
     }
+    pub fn gameboy_mode(&self) -> GameBoyMode {
+        self.vram.mode
+    }
+    // only GBC hardware has the register
+    fn opri(&self) -> u8 {
+        if self.vram.mode == GameBoyMode::GB {
+            0xFF
+        } else {
+            self.opri | 0xFE
+        }
+    }
+    // The enabled sources share one line and only its rising edge requests the interrupt, a
+    // source that is already up blocks the ones coming after it.
     pub fn set_mode_interrupt(&mut self) {
-        let mode = self.stat.mode_flag;
-        match mode {
-            OAMScan => {
-                let enable = self.stat.mode2_interrupt;
-                if enable {
-                    self.interrupt_flag_lcdstat = true;
-                }
-            }
-            VBlank => {
-                let enable = self.stat.mode1_interrupt;
-                if enable {
-                    self.interrupt_flag_lcdstat = true;
-                }
-                self.interrupt_flag_vblank = true;
-            }
-            HBlank => {
-                let enable = self.stat.mode0_interrupt;
-                if enable {
-                    self.interrupt_flag_lcdstat = true;
-                }
-            }
-            Drawing => {}
+        if self.update_stat_line() {
+            self.interrupt_flag_lcdstat = true;
+        }
+    }
+    // true on a rising edge of the line
+    fn update_stat_line(&mut self) -> bool {
+        let mode = match self.stat.mode_flag {
+            HBlank => self.stat.mode0_interrupt && self.hblank_interrupt,
+            VBlank => self.stat.mode1_interrupt,
+            _ => false,
         };
+        let oam = self.stat.mode2_interrupt && self.oam_interrupt;
+        if self.lcdc.lcd_ppu_enable {
+            self.lyc_flag = self.ly == self.lyc;
+        }
+        let lyc = self.stat.lyc_ly_interrupt && self.lyc_flag;
+        let line = (self.lcdc.lcd_ppu_enable && (mode || oam)) || lyc;
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+    fn set_ly(&mut self, ly: u8) {
+        self.ly = ly;
+        self.set_mode_interrupt();
+    }
+    fn set_oam_interrupt(&mut self, oam_interrupt: bool) {
+        self.oam_interrupt = oam_interrupt;
+        self.set_mode_interrupt();
+    }
+    fn set_hblank_interrupt(&mut self) {
+        self.hblank_interrupt = true;
+        self.set_mode_interrupt();
     }
-    pub fn set_ly_interrupt(&mut self) {
-        let ly = self.ly;
-        let lyc = self.lyc;
-        if ly == lyc {
-            let enable = self.stat.lyc_ly_interrupt;
-            if enable {
-                self.interrupt_flag_lcdstat = true;
+    // the cpu can't reach OAM while the PPU scans or draws from it, nor VRAM while it draws
+    pub fn is_locked(&self, index: u16) -> bool {
+        if !self.lcdc.lcd_ppu_enable {
+            return false;
+        }
+        matches!(
+            (index, self.stat.mode_flag),
+            (0x8000..=0x9FFF, Drawing) | (0xFE00..=0xFE9F, OAMScan | Drawing)
+        )
+    }
+    // bank 1 only exists on GBC
+    pub fn peek_vram(&self, bank: usize, index: u16) -> u8 {
+        let index = 0x8000 | (index & 0x1FFF);
+        match bank {
+            0 => self.vram.get_by_bank(index, false),
+            1 if self.vram.mode == GameBoyMode::GBC => self.vram.get_by_bank(index, true),
+            _ => 0xFF,
+        }
+    }
+    // a register as it would read afterwards. STAT and LYC move the interrupt line without
+    // requesting the interrupt and the palette data doesn't move the index on.
+    pub(crate) fn store(&mut self, index: u16, value: u8) {
+        match index {
+            0xFF41 => {
+                self.stat.set(index, value);
+                self.update_stat_line();
+            }
+            0xFF45 => {
+                self.lyc = value;
+                self.update_stat_line();
             }
+            0xFF69 => self.bcp.memory[self.bcp.address as usize] = value,
+            0xFF6B => self.ocp.memory[self.ocp.address as usize] = value,
+            _ => self.set(index, value),
         }
     }
 }
@@ -1583,7 +1764,7 @@ impl Memory for PpuMmu {
         match index {
             0xFF40 => self.lcdc.get(index),
             0xFF41 => {
-                let lyc_ly_flag_bit = (self.ly == self.lyc) as u8;
+                let lyc_ly_flag_bit = self.lyc_flag as u8;
                 self.stat.get(index) | (lyc_ly_flag_bit << 2)
             }
             0xFF42 => self.scy,
@@ -1598,6 +1779,7 @@ impl Memory for PpuMmu {
             0xFF4F | 0x8000..=0x9FFF => self.vram.get(index),
             0xFF68 | 0xFF69 => self.bcp.get(index),
             0xFF6A | 0xFF6B => self.ocp.get(index),
+            0xFF6C => self.opri(),
             0xFE00..=0xFE9F => self.oam[(index - 0xFE00) as usize],
             _ => panic!("PpuMmu out of range"),
         }
@@ -1605,11 +1787,17 @@ impl Memory for PpuMmu {
     fn set(&mut self, index: u16, value: u8) {
         match index {
             0xFF40 => self.lcdc.set(index, value),
-            0xFF41 => self.stat.set(index, value),
+            0xFF41 => {
+                self.stat.set(index, value);
+                self.set_mode_interrupt();
+            }
             0xFF42 => self.scy = value,
             0xFF43 => self.scx = value,
             0xFF44 => {}
-            0xFF45 => self.lyc = value,
+            0xFF45 => {
+                self.lyc = value;
+                self.set_mode_interrupt();
+            }
             0xFF47 => self.bgp = value,
             0xFF48 => self.op0 = value,
             0xFF49 => self.op1 = value,
@@ -1618,6 +1806,11 @@ impl Memory for PpuMmu {
             0xFF4F | 0x8000..=0x9FFF => self.vram.set(index, value),
             0xFF68 | 0xFF69 => self.bcp.set(index, value),
             0xFF6A | 0xFF6B => self.ocp.set(index, value),
+            0xFF6C => {
+                if self.vram.mode != GameBoyMode::GB {
+                    self.opri = value & 1;
+                }
+            }
             0xFE00..=0xFE9F => self.oam[(index - 0xFE00) as usize] = value,
             _ => panic!("PpuMmu out of range"),
         }
@@ -1,7 +1,8 @@
 mod test {
     mod gb_test_roms {
         macro_rules! test {
-            ($func: ident, $path:expr, $game:expr, $expect:expr) => {
+            ($(#[$attr:meta])* $func: ident, $path:expr, $game:expr, $expect:expr) => {
+                $(#[$attr])*
                 #[test]
                 fn $func() {
                     use crate::gameboy::GameBoy;
@@ -20,7 +21,7 @@ mod test {
                         .unwrap()
                         .as_secs();
                     let mut clocks: usize = 0;
-                    while gameboy.mmu.borrow().log_msg.len() < expect.len() {
+                    while gameboy.mmu().log_msg.len() < expect.len() {
                         gameboy.trick();
                         clocks += 1;
                         if clocks >= 100000 {
@@ -34,12 +35,12 @@ mod test {
                             clocks = 0;
                         }
                     }
-                    let str = &gameboy.mmu.borrow().log_msg;
+                    let str = &gameboy.mmu().log_msg;
                     assert_eq!(&str[..], &expect[..]);
                 }
             };
-            ($func: ident, $path:expr, $game:expr) => {
-                test!($func, $path, $game, format!("{}\n\n\nPassed", $game));
+            ($(#[$attr:meta])* $func: ident, $path:expr, $game:expr) => {
+                test!($(#[$attr])* $func, $path, $game, format!("{}\n\n\nPassed", $game));
             };
         }
         mod cpu_instrs {
@@ -72,14 +73,44 @@ mod test {
         mod instr_timing {
             test!(test_instr_timing, "instr_timing/", "instr_timing");
         }
+        // the roms aren't checked in, run these with --ignored once they are in tests/gb-test-roms
+        mod halt_bug {
+            test!(
+                #[ignore = "needs tests/gb-test-roms"]
+                test_halt_bug,
+                "",
+                "halt_bug"
+            );
+        }
+        mod mem_timing {
+            test!(
+                #[ignore = "needs tests/gb-test-roms"]
+                test_mem_timing,
+                "mem_timing/",
+                "mem_timing",
+                "mem_timing\n\n01:ok  02:ok  03:ok  \n\nPassed all tests"
+            );
+            test!(
+                #[ignore = "needs tests/gb-test-roms"]
+                test_mem_timing_2,
+                "mem_timing-2/",
+                "mem_timing",
+                "mem_timing\n\n01:ok  02:ok  03:ok  \n\nPassed all tests"
+            );
+        }
     }
 
     mod mooneye_test_suite {
         macro_rules! test {
             ($func: ident, $path:expr, $game:expr) => {
+                test!($func, $path, $game, None);
+            };
+            ($func: ident, $path:expr, $game:expr, $model:expr) => {
                 #[test]
                 fn $func() {
                     use crate::gameboy::GameBoy;
+                    #[allow(unused_imports)]
+                    use crate::gameboy::Model;
                     use crate::util::read_rom;
                     use std::time::SystemTime;
                     let bios_path = "";
@@ -87,14 +118,17 @@ mod test {
                     let bios = read_rom(bios_path).unwrap_or(vec![]);
                     let rom = read_rom(rom_path).unwrap();
                     let cartridge = GameBoy::get_cartridge(rom);
-                    let mut gameboy = GameBoy::new(bios, cartridge);
+                    let mut gameboy = match $model {
+                        Some(model) => GameBoy::with_model(model, bios, cartridge),
+                        None => GameBoy::new(bios, cartridge),
+                    };
                     let expect = vec![3, 5, 8, 13, 21, 34];
                     let start = SystemTime::now()
                         .duration_since(SystemTime::UNIX_EPOCH)
                         .unwrap()
                         .as_secs();
                     let mut clocks: usize = 0;
-                    while gameboy.mmu.borrow().log_msg.len() < expect.len() {
+                    while gameboy.mmu().log_msg.len() < expect.len() {
                         gameboy.trick();
                         clocks += 1;
                         if clocks >= 100000 {
@@ -108,28 +142,113 @@ mod test {
                             clocks = 0;
                         }
                     }
-                    let str = &gameboy.mmu.borrow().log_msg;
+                    let str = &gameboy.mmu().log_msg;
                     assert_eq!(&str[..], &expect[..]);
                 }
             };
         }
 
         mod acceptance {
+            mod boot {
+                test!(boot_div_dmg0, "acceptance/", "boot_div-dmg0", Some(Model::DMG0));
+                test!(boot_div_dmg_abc_mgb, "acceptance/", "boot_div-dmgABCmgb", Some(Model::DMG));
+                test!(boot_div_s, "acceptance/", "boot_div-S", Some(Model::SGB));
+                test!(boot_hwio_dmg_abc_mgb, "acceptance/", "boot_hwio-dmgABCmgb", Some(Model::DMG));
+                test!(boot_hwio_s, "acceptance/", "boot_hwio-S", Some(Model::SGB));
+                test!(boot_regs_dmg0, "acceptance/", "boot_regs-dmg0", Some(Model::DMG0));
+                test!(boot_regs_dmg_abc, "acceptance/", "boot_regs-dmgABC", Some(Model::DMG));
+                test!(boot_regs_mgb, "acceptance/", "boot_regs-mgb", Some(Model::MGB));
+                test!(boot_regs_sgb, "acceptance/", "boot_regs-sgb", Some(Model::SGB));
+                test!(boot_regs_sgb2, "acceptance/", "boot_regs-sgb2", Some(Model::SGB2));
+            }
+
             mod bits {
                 test!(mem_oam, "acceptance/bits/", "mem_oam");
                 test!(reg_f, "acceptance/bits/", "reg_f");
+                test!(unused_hwio_gs, "acceptance/bits/", "unused_hwio-GS");
             }
 
             mod instr {
                 test!(daa, "acceptance/instr/", "daa");
             }
 
+            mod halt {
+                test!(halt_ime0_ei, "acceptance/", "halt_ime0_ei");
+                test!(halt_ime0_nointr_timing, "acceptance/", "halt_ime0_nointr_timing");
+                test!(halt_ime1_timing, "acceptance/", "halt_ime1_timing");
+            }
+
+            mod timing {
+                test!(add_sp_e_timing, "acceptance/", "add_sp_e_timing");
+                test!(call_cc_timing, "acceptance/", "call_cc_timing");
+                test!(call_cc_timing2, "acceptance/", "call_cc_timing2");
+                test!(call_timing, "acceptance/", "call_timing");
+                test!(call_timing2, "acceptance/", "call_timing2");
+                test!(div_timing, "acceptance/", "div_timing");
+                test!(ei_sequence, "acceptance/", "ei_sequence");
+                test!(ei_timing, "acceptance/", "ei_timing");
+                test!(if_ie_registers, "acceptance/", "if_ie_registers");
+                test!(intr_timing, "acceptance/", "intr_timing");
+                test!(jp_cc_timing, "acceptance/", "jp_cc_timing");
+                test!(jp_timing, "acceptance/", "jp_timing");
+                test!(ld_hl_sp_e_timing, "acceptance/", "ld_hl_sp_e_timing");
+                test!(pop_timing, "acceptance/", "pop_timing");
+                test!(push_timing, "acceptance/", "push_timing");
+                test!(ret_cc_timing, "acceptance/", "ret_cc_timing");
+                test!(ret_timing, "acceptance/", "ret_timing");
+                test!(reti_timing, "acceptance/", "reti_timing");
+                test!(rst_timing, "acceptance/", "rst_timing");
+            }
+
+            mod ppu {
+                test!(hblank_ly_scx_timing_gs, "acceptance/ppu/", "hblank_ly_scx_timing-GS");
+                test!(intr_1_2_timing_gs, "acceptance/ppu/", "intr_1_2_timing-GS");
+                test!(intr_2_0_timing, "acceptance/ppu/", "intr_2_0_timing");
+                test!(intr_2_mode0_timing, "acceptance/ppu/", "intr_2_mode0_timing");
+                test!(intr_2_mode0_timing_sprites, "acceptance/ppu/", "intr_2_mode0_timing_sprites");
+                test!(intr_2_mode3_timing, "acceptance/ppu/", "intr_2_mode3_timing");
+                test!(intr_2_oam_ok_timing, "acceptance/ppu/", "intr_2_oam_ok_timing");
+                // test!(lcdon_timing_gs, "acceptance/ppu/", "lcdon_timing-GS");
+                // test!(lcdon_write_timing_gs, "acceptance/ppu/", "lcdon_write_timing-GS");
+                test!(stat_irq_blocking, "acceptance/ppu/", "stat_irq_blocking");
+                test!(stat_lyc_onoff, "acceptance/ppu/", "stat_lyc_onoff");
+                test!(vblank_stat_intr_gs, "acceptance/ppu/", "vblank_stat_intr-GS");
+            }
+
+            mod timer {
+                test!(div_write, "acceptance/timer/", "div_write");
+                test!(rapid_toggle, "acceptance/timer/", "rapid_toggle");
+                test!(tim00, "acceptance/timer/", "tim00");
+                test!(tim00_div_trigger, "acceptance/timer/", "tim00_div_trigger");
+                test!(tim01, "acceptance/timer/", "tim01");
+                test!(tim01_div_trigger, "acceptance/timer/", "tim01_div_trigger");
+                test!(tim10, "acceptance/timer/", "tim10");
+                test!(tim10_div_trigger, "acceptance/timer/", "tim10_div_trigger");
+                test!(tim11, "acceptance/timer/", "tim11");
+                test!(tim11_div_trigger, "acceptance/timer/", "tim11_div_trigger");
+                test!(tima_reload, "acceptance/timer/", "tima_reload");
+                test!(tima_write_reloading, "acceptance/timer/", "tima_write_reloading");
+                test!(tma_write_reloading, "acceptance/timer/", "tma_write_reloading");
+            }
+
             mod oam_dma {
                 test!(basic, "acceptance/oam_dma/", "basic");
                 test!(reg_read, "acceptance/oam_dma/", "reg_read");
+                test!(oam_dma_restart, "acceptance/", "oam_dma_restart");
+                test!(oam_dma_start, "acceptance/", "oam_dma_start");
+                test!(oam_dma_timing, "acceptance/", "oam_dma_timing");
+                test!(sources_gs, "acceptance/oam_dma/", "sources-GS");
             }
         }
 
+        mod misc {
+            test!(boot_div_a, "misc/", "boot_div-A", Some(Model::AGB));
+            test!(boot_div_cgb_abcde, "misc/", "boot_div-cgbABCDE", Some(Model::CGB));
+            test!(boot_hwio_c, "misc/", "boot_hwio-C", Some(Model::CGB));
+            test!(boot_regs_a, "misc/", "boot_regs-A", Some(Model::AGB));
+            test!(boot_regs_cgb, "misc/", "boot_regs-cgb", Some(Model::CGB));
+        }
+
         mod emulator_only {
             mod mbc1 {
                 test!(bits_bank1, "emulator-only/mbc1/", "bits_bank1");
@@ -255,4 +374,1625 @@ mod test {
             test!(cgb_acid2, "cgb-acid2/", "cgb-acid2.gbc");
         }
     }
+
+    mod apu {
+        #[test]
+        fn read_mask() {
+            use crate::apu::Apu;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            let mut apu = Apu::new(GameBoyMode::GB);
+            apu.set(0xFF26, 0x80);
+            let masks = [
+                0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF,
+                0xBF, 0xFF, 0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0xF0,
+            ];
+            for (offset, mask) in masks.iter().enumerate() {
+                let index = 0xFF10 + offset as u16;
+                if index != 0xFF26 {
+                    apu.set(index, 0x00);
+                }
+                assert_eq!(apu.get(index), *mask, "register {:04X}", index);
+            }
+        }
+
+        #[test]
+        fn power_off() {
+            use crate::apu::Apu;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            let mut apu = Apu::new(GameBoyMode::GB);
+            apu.set(0xFF26, 0x80);
+            apu.set(0xFF12, 0xF0);
+            apu.set(0xFF14, 0x80);
+            assert_eq!(apu.get(0xFF26), 0xF1);
+            apu.set(0xFF30, 0x12);
+            apu.set(0xFF26, 0x00);
+            assert_eq!(apu.get(0xFF26), 0x70);
+            assert_eq!(apu.get(0xFF12), 0x00);
+            apu.set(0xFF12, 0xF0);
+            assert_eq!(apu.get(0xFF12), 0x00);
+            assert_eq!(apu.get(0xFF30), 0x12);
+        }
+
+        #[test]
+        fn sample_rate() {
+            use crate::apu::{Apu, CLOCK_FREQUENCY};
+            use crate::gameboy_mode::GameBoyMode;
+            let mut apu = Apu::new(GameBoyMode::GB);
+            apu.trick();
+            assert!(apu.take_samples().is_empty());
+            apu.set_sample_rate(44100);
+            for _ in 0..CLOCK_FREQUENCY {
+                apu.trick();
+            }
+            assert_eq!(apu.take_samples().len(), 44100 * 2);
+            assert!(apu.take_samples().is_empty());
+        }
+
+        #[test]
+        fn length_counter() {
+            use crate::apu::Apu;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            let mut apu = Apu::new(GameBoyMode::GB);
+            apu.set(0xFF26, 0x80);
+            apu.set(0xFF12, 0xF0);
+            apu.set(0xFF11, 0x3E);
+            apu.set(0xFF14, 0xC0);
+            assert_eq!(apu.get(0xFF26) & 0x01, 0x01);
+            for _ in 0..3 {
+                apu.trick_frame_sequencer();
+            }
+            assert_eq!(apu.get(0xFF26) & 0x01, 0x00);
+        }
+
+        #[test]
+        fn length_counter_power() {
+            use crate::apu::Apu;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            for (mode, status) in [(GameBoyMode::GB, 0x00), (GameBoyMode::GBC, 0x01)] {
+                let mut apu = Apu::new(mode);
+                apu.set(0xFF26, 0x80);
+                apu.set(0xFF26, 0x00);
+                // only the DMG accepts length writes while powered off
+                apu.set(0xFF11, 0x3F);
+                apu.set(0xFF26, 0x80);
+                apu.set(0xFF12, 0xF0);
+                apu.set(0xFF14, 0xC0);
+                apu.trick_frame_sequencer();
+                assert_eq!(apu.get(0xFF26) & 0x01, status);
+            }
+        }
+
+        #[test]
+        fn pcm() {
+            use crate::apu::Apu;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            let mut apu = Apu::new(GameBoyMode::GBC);
+            apu.set(0xFF26, 0x80);
+            assert_eq!(apu.get(0xFF76), 0x00);
+            // the first step of 50% duty is high
+            apu.set(0xFF16, 0x80);
+            apu.set(0xFF17, 0xA0);
+            apu.set(0xFF19, 0x80);
+            assert_eq!(apu.get(0xFF76), 0xA0);
+            assert_eq!(apu.get(0xFF77), 0x00);
+            let apu = Apu::new(GameBoyMode::GB);
+            assert_eq!(apu.get(0xFF76), 0xFF);
+        }
+
+        #[test]
+        fn wave_ram_while_playing() {
+            use crate::apu::Apu;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            for mode in [GameBoyMode::GB, GameBoyMode::GBC] {
+                let mut apu = Apu::new(mode);
+                for index in 0..16 {
+                    apu.set(0xFF30 + index, index as u8 * 0x11);
+                }
+                apu.set(0xFF26, 0x80);
+                apu.set(0xFF1A, 0x80);
+                apu.set(0xFF1D, 0x00);
+                apu.set(0xFF1E, 0x87);
+                // the first fetch reads position 1
+                for _ in 0..2 * 0x100 + 6 {
+                    apu.trick();
+                }
+                assert_eq!(apu.get(0xFF3F), 0x00);
+                for _ in 0..0x100 {
+                    apu.trick();
+                }
+                let expect = if mode == GameBoyMode::GBC { 0x00 } else { 0xFF };
+                assert_eq!(apu.get(0xFF3F), expect);
+                apu.set(0xFF1A, 0x00);
+                assert_eq!(apu.get(0xFF3F), 0xFF);
+                assert_eq!(apu.get(0xFF30), 0x00);
+            }
+        }
+    }
+
+    mod apu_channel {
+        #[test]
+        fn mute_solo() {
+            use crate::apu::Apu;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            let mut apu = Apu::new(GameBoyMode::GB);
+            apu.set(0xFF26, 0x80);
+            apu.set(0xFF24, 0x77);
+            apu.set(0xFF25, 0xFF);
+            // square 2 at full volume with 75% duty, frequency 0x700
+            apu.set(0xFF16, 0xC0);
+            apu.set(0xFF17, 0xF0);
+            apu.set(0xFF18, 0x00);
+            apu.set(0xFF19, 0x87);
+            let state = apu.channel_state(1).unwrap();
+            assert!(state.enable);
+            assert_eq!(state.frequency, 0x700);
+            assert_eq!(state.frequency_hz, 512.0);
+            assert_eq!(state.volume, 0x0F);
+            assert_eq!(state.duty, 3);
+            assert!(!apu.channel_state(0).unwrap().enable);
+            assert!(apu.channel_state(4).is_none());
+
+            let (left, _) = apu.output();
+            apu.set_channel_muted(1, true);
+            assert!(apu.channel_state(1).unwrap().muted);
+            apu.set_channel_muted(4, true);
+            apu.set_channel_solo(4, true);
+            assert!(!apu.is_channel_audible(4));
+            assert!(apu.take_channel_samples(4).is_empty());
+            assert_ne!(apu.output().0, left);
+            apu.set_channel_muted(1, false);
+            apu.set_channel_solo(0, true);
+            assert!(!apu.is_channel_audible(1));
+            assert_ne!(apu.output().0, left);
+            apu.set_channel_solo(1, true);
+            assert!(apu.is_channel_audible(1));
+            assert_eq!(apu.output().0, left);
+        }
+        #[test]
+        fn frame_sequencer_div() {
+            use crate::bus::Bus;
+            use crate::mmu::Mmu;
+            let mut mmu = Mmu::default();
+            mmu.poke(0xFF26, 0x80);
+            // square 2 with a length of 2
+            mmu.poke(0xFF16, 0x3E);
+            mmu.poke(0xFF17, 0xF0);
+            mmu.poke(0xFF19, 0xC7);
+            // DIV bit 4 is high, writing DIV makes it fall and clocks the length
+            for _ in 0..1500 {
+                mmu.tick();
+            }
+            mmu.poke(0xFF04, 0x00);
+            assert!(mmu.apu.channel_state(1).unwrap().enable);
+            // falling edges follow every 2048 cycles from the write, the second one clocks the
+            // length again
+            for _ in 0..4095 {
+                mmu.tick();
+            }
+            assert!(mmu.apu.channel_state(1).unwrap().enable);
+            mmu.tick();
+            assert!(!mmu.apu.channel_state(1).unwrap().enable);
+        }
+    }
+
+    mod debugger {
+        #[cfg(test)]
+        fn tetris() -> crate::gameboy::GameBoy {
+            use crate::gameboy::GameBoy;
+            use crate::util::read_rom;
+            let rom = read_rom("../tests/Tetris.gb").unwrap();
+            GameBoy::new(vec![], GameBoy::get_cartridge(rom))
+        }
+
+        #[test]
+        fn step() {
+            use crate::debugger::{Debugger, StopReason};
+            let mut gameboy = tetris();
+            let debugger = Debugger::new();
+            assert_eq!(gameboy.registers().pc, 0x0100);
+            // NOP; JP $0150
+            assert_eq!(debugger.step(&mut gameboy), StopReason::Step);
+            assert_eq!(gameboy.registers().pc, 0x0101);
+            assert_eq!(debugger.step(&mut gameboy), StopReason::Step);
+            assert_eq!(gameboy.registers().pc, 0x0150);
+        }
+
+        #[test]
+        fn step_over_and_out() {
+            use crate::debugger::{Debugger, StopReason};
+            let mut gameboy = tetris();
+            let debugger = Debugger::new();
+            let mut steps = 0;
+            while gameboy.peek(gameboy.registers().pc) != 0xCD {
+                debugger.step(&mut gameboy);
+                steps += 1;
+                assert!(steps < 100000, "no CALL found");
+            }
+            let registers = gameboy.registers();
+            assert_eq!(debugger.step_over(&mut gameboy), StopReason::Step);
+            assert_eq!(gameboy.registers().pc, registers.pc + 3);
+            assert_eq!(gameboy.registers().sp, registers.sp);
+
+            while gameboy.peek(gameboy.registers().pc) != 0xCD {
+                debugger.step(&mut gameboy);
+            }
+            let registers = gameboy.registers();
+            debugger.step(&mut gameboy);
+            assert_eq!(gameboy.registers().sp, registers.sp - 2);
+            assert_eq!(debugger.step_out(&mut gameboy), StopReason::Step);
+            assert_eq!(gameboy.registers().pc, registers.pc + 3);
+            assert_eq!(gameboy.registers().sp, registers.sp);
+        }
+
+        #[test]
+        fn cpu_state() {
+            use crate::debugger::Debugger;
+            use crate::gameboy::CpuState;
+            use crate::memory::Memory;
+            let mut gameboy = tetris();
+            let debugger = Debugger::new();
+            // INC A
+            gameboy.mmu_mut().set(0xC000, 0x3C);
+            let state = CpuState {
+                af: 0x12FF,
+                bc: 0x3456,
+                pc: 0xC000,
+                sp: 0xDFF0,
+                ..gameboy.cpu_state()
+            };
+            gameboy.set_cpu_state(state);
+            assert_eq!(gameboy.cpu_state().af, 0x12F0);
+            debugger.step(&mut gameboy);
+            let after = gameboy.cpu_state();
+            assert_eq!(after.af, 0x1310);
+            assert_eq!(after.bc, 0x3456);
+            assert_eq!(after.pc, 0xC001);
+            assert_eq!(after.sp, 0xDFF0);
+
+            gameboy.set_cpu_state(CpuState {
+                ime: true,
+                halted: true,
+                double_speed: true,
+                ..after
+            });
+            let state = gameboy.cpu_state();
+            assert!(state.ime && state.halted && state.double_speed);
+            assert!(gameboy.is_halted());
+        }
+
+        #[test]
+        fn breakpoint_and_watchpoint() {
+            use crate::debugger::{
+                Access, Breakpoint, Debugger, StopReason, WatchKind, Watchpoint,
+            };
+            let mut gameboy = tetris();
+            let mut debugger = Debugger::new();
+            assert_eq!(debugger.run_to_frame(&mut gameboy), StopReason::Frame);
+
+            let mut gameboy = tetris();
+            let breakpoint = Breakpoint {
+                bank: Some(0),
+                address: 0x0150,
+            };
+            debugger.add_breakpoint(breakpoint);
+            assert_eq!(
+                debugger.run_to_frame(&mut gameboy),
+                StopReason::Breakpoint(breakpoint)
+            );
+            // a breakpoint in another bank doesn't stop
+            debugger.remove_breakpoint(breakpoint);
+            debugger.add_breakpoint(Breakpoint {
+                bank: Some(1),
+                address: 0x0150,
+            });
+            let mut gameboy = tetris();
+            debugger.add_watchpoint(Watchpoint {
+                address: 0xFF40,
+                kind: WatchKind::Write,
+            });
+            // clearing WRAM takes a few frames before LCDC is written
+            let mut reason = debugger.run_to_frame(&mut gameboy);
+            for _ in 0..10 {
+                if reason != StopReason::Frame && reason != StopReason::Timeout {
+                    break;
+                }
+                reason = debugger.run_to_frame(&mut gameboy);
+            }
+            match reason {
+                StopReason::Watchpoint {
+                    pc,
+                    address,
+                    value,
+                    access,
+                } => {
+                    // LDH ($FF40),A
+                    assert_eq!(gameboy.peek(pc), 0xE0);
+                    assert_eq!(gameboy.peek(pc.wrapping_add(1)), 0x40);
+                    assert_eq!(address, 0xFF40);
+                    assert_eq!(value, 0x80);
+                    assert_eq!(access, Access::Write);
+                }
+                reason => panic!("unexpected {:?}", reason),
+            }
+        }
+
+        #[test]
+        fn fetch_watchpoint() {
+            use crate::debugger::{Access, Debugger, StopReason, WatchKind, Watchpoint};
+            let mut gameboy = tetris();
+            let mut debugger = Debugger::new();
+            // executing the NOP at $0100 is not a read
+            debugger.add_watchpoint(Watchpoint {
+                address: 0x0100,
+                kind: WatchKind::ReadWrite,
+            });
+            assert_eq!(debugger.step(&mut gameboy), StopReason::Step);
+
+            let mut gameboy = tetris();
+            debugger.add_watchpoint(Watchpoint {
+                address: 0x0100,
+                kind: WatchKind::Execute,
+            });
+            assert_eq!(
+                debugger.step(&mut gameboy),
+                StopReason::Watchpoint {
+                    pc: 0x0100,
+                    address: 0x0100,
+                    value: 0x00,
+                    access: Access::Fetch,
+                }
+            );
+        }
+
+        #[test]
+        fn timeout() {
+            use crate::debugger::{Debugger, StopReason};
+            use crate::memory::Memory;
+            let mut gameboy = tetris();
+            let debugger = Debugger::new();
+            // the entry point never returns
+            assert_eq!(debugger.step_out(&mut gameboy), StopReason::Timeout);
+
+            // turning the LCD off shows one blank frame, then a frame is a frame worth of cycles
+            gameboy.mmu_mut().set(0xFF40, 0x00);
+            assert_eq!(debugger.run_to_frame(&mut gameboy), StopReason::Frame);
+            assert_eq!(debugger.run_to_frame(&mut gameboy), StopReason::Frame);
+            // from the middle of a frame with the LCD on again
+            gameboy.mmu_mut().set(0xFF40, 0x91);
+            for _ in 0..100 {
+                debugger.step(&mut gameboy);
+            }
+            assert_eq!(debugger.run_to_frame(&mut gameboy), StopReason::Frame);
+        }
+    }
+
+    mod disasm {
+        #[test]
+        fn mnemonics() {
+            use crate::disasm::disassemble;
+            let cases = [
+                (vec![0x00], "NOP", 1, 1, None),
+                (vec![0xC3, 0x50, 0x01], "JP $0150", 3, 4, Some(0x0150)),
+                (vec![0x20, 0xFE], "JR NZ,$0200", 2, 2, Some(0x0200)),
+                (vec![0xCD, 0x34, 0x12], "CALL $1234", 3, 6, Some(0x1234)),
+                (vec![0xE0, 0x40], "LDH ($FF40),A", 2, 3, None),
+                (vec![0x3A], "LD A,(HL-)", 1, 2, None),
+                (vec![0xF8, 0xFD], "LD HL,SP-$03", 2, 3, None),
+                (vec![0xF8, 0x05], "LD HL,SP+$05", 2, 3, None),
+                (vec![0xE8, 0x80], "ADD SP,-$80", 2, 4, None),
+                (vec![0xE8, 0x05], "ADD SP,+$05", 2, 4, None),
+                (vec![0xCB, 0x7C], "BIT 7,H", 2, 2, None),
+                (vec![0xCB, 0x36], "SWAP (HL)", 2, 4, None),
+                (vec![0xFF], "RST $38", 1, 4, Some(0x0038)),
+                (vec![0x76], "HALT", 1, 1, None),
+                (vec![0xD3], "DB $D3", 1, 0, None),
+            ];
+            for (bytes, mnemonic, length, cycles, target) in cases {
+                let instruction = disassemble(0x0200, &bytes);
+                assert_eq!(instruction.mnemonic, mnemonic);
+                assert_eq!(instruction.length(), length, "{}", mnemonic);
+                assert_eq!(instruction.cycles, cycles, "{}", mnemonic);
+                assert_eq!(instruction.target, target, "{}", mnemonic);
+            }
+        }
+
+        #[test]
+        fn truncated() {
+            use crate::disasm::{disassemble, disassemble_block};
+            let instruction = disassemble(0x0200, &[0xC3, 0x50]);
+            assert!(instruction.truncated);
+            assert_eq!(instruction.bytes, vec![0xC3, 0x50]);
+            assert!(!disassemble(0x0200, &[0xC3, 0x50, 0x01]).truncated);
+            // the block stops at the end of the input
+            let instructions = disassemble_block(0x0200, &[0x00, 0xCD, 0x34]);
+            assert_eq!(instructions.len(), 2);
+            assert_eq!(instructions[1].bytes, vec![0xCD, 0x34]);
+            assert!(instructions[1].truncated);
+        }
+    }
+
+    mod trace {
+        #[test]
+        fn doctor_format() {
+            use crate::gameboy::GameBoy;
+            use crate::util::read_rom;
+            use std::cell::RefCell;
+            use std::io::Write;
+            use std::rc::Rc;
+
+            struct Shared(Rc<RefCell<Vec<u8>>>);
+            impl Write for Shared {
+                fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                    self.0.borrow_mut().write(buf)
+                }
+                fn flush(&mut self) -> std::io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            let rom = read_rom("../tests/Tetris.gb").unwrap();
+            let mut gameboy = GameBoy::new(vec![], GameBoy::get_cartridge(rom));
+            let buffer = Rc::new(RefCell::new(vec![]));
+            gameboy.set_trace(Some(Box::new(Shared(buffer.clone()))));
+            for _ in 0..4 * 5 {
+                gameboy.trick();
+            }
+            gameboy.set_trace(None);
+            let log = String::from_utf8(buffer.borrow().clone()).unwrap();
+            let lines: Vec<&str> = log.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].ends_with("SP:FFFE PC:0100 PCMEM:00,C3,50,01"));
+            assert!(lines[1].ends_with("SP:FFFE PC:0101 PCMEM:C3,50,01,CE"));
+            assert!(lines[0].starts_with("A:01 F:"));
+        }
+    }
+
+    mod timer {
+        #[test]
+        fn div_reset_glitch() {
+            use crate::memory::Memory;
+            use crate::timer::Timer;
+            let mut timer = Timer::new();
+            // 262144Hz, bit 3 of the counter
+            timer.set(0xFF07, 0x05);
+            for _ in 0..4 {
+                timer.tick();
+            }
+            assert_eq!(timer.get(0xFF05), 0x01);
+            timer.tick();
+            timer.tick();
+            // bit 3 is high, clearing the counter makes it fall
+            timer.set(0xFF04, 0x00);
+            assert_eq!(timer.get(0xFF05), 0x02);
+            assert_eq!(timer.get(0xFF04), 0x00);
+        }
+        #[test]
+        fn overflow_reload() {
+            use crate::memory::Memory;
+            use crate::timer::Timer;
+            let mut timer = Timer::new();
+            timer.set(0xFF06, 0x80);
+            timer.set(0xFF05, 0xFF);
+            timer.set(0xFF07, 0x05);
+            for _ in 0..4 {
+                timer.tick();
+            }
+            // TIMA reads 0 for a cycle before it's reloaded
+            assert_eq!(timer.get(0xFF05), 0x00);
+            assert!(!timer.interrupt_flag);
+            timer.tick();
+            assert_eq!(timer.get(0xFF05), 0x80);
+            assert!(timer.interrupt_flag);
+        }
+    }
+
+    mod bus {
+        #[test]
+        fn tick_per_cycle() {
+            use crate::bus::{FlatBus, RecordingBus};
+            use crate::cpu::Cpu;
+            use crate::debugger::Access;
+            use crate::gameboy_mode::Model;
+            use crate::memory::Memory;
+            let mut cpu = Cpu::new(Model::DMG, RecordingBus::new(FlatBus::new()), true);
+            // LD (a16),A; PUSH BC
+            for (index, value) in [0xEA, 0x00, 0xC0, 0xC5].iter().enumerate() {
+                cpu.bus.inner.set(0x0100 + index as u16, *value);
+            }
+            // the first trick runs the whole instruction
+            cpu.trick();
+            assert_eq!(cpu.bus.ticks(), 4);
+            assert_eq!(
+                cpu.bus.take().last(),
+                Some(&(4, 0xC000, 0x01, Access::Write))
+            );
+            while !cpu.is_instruction_boundary() {
+                cpu.trick();
+            }
+            // the writes of a push follow an internal cycle
+            cpu.trick();
+            assert_eq!(cpu.bus.ticks(), 8);
+            assert_eq!(
+                cpu.bus.take(),
+                vec![
+                    (5, 0x0103, 0xC5, Access::Fetch),
+                    (7, 0xFFFD, 0x00, Access::Write),
+                    (8, 0xFFFC, 0x13, Access::Write),
+                ]
+            );
+        }
+    }
+
+    mod halt {
+        #[cfg(test)]
+        fn flat(code: &[u8]) -> crate::cpu::Cpu<crate::bus::FlatBus> {
+            use crate::bus::FlatBus;
+            use crate::cpu::Cpu;
+            use crate::gameboy_mode::Model;
+            use crate::memory::Memory;
+            let mut cpu = Cpu::new(Model::DMG, FlatBus::new(), true);
+            for (index, value) in code.iter().enumerate() {
+                cpu.bus.set(0x0100 + index as u16, *value);
+            }
+            cpu
+        }
+        #[cfg(test)]
+        pub(super) fn rom(code: &[u8], gbc: bool) -> crate::gameboy::GameBoy {
+            use crate::gameboy::GameBoy;
+            let mut rom = vec![0; 0x8000];
+            rom[0x0100..0x0100 + code.len()].copy_from_slice(code);
+            if gbc {
+                rom[0x0143] = 0x80;
+            }
+            GameBoy::new(vec![], GameBoy::get_cartridge(rom))
+        }
+        #[cfg(test)]
+        fn step<B: crate::bus::Bus>(cpu: &mut crate::cpu::Cpu<B>) {
+            cpu.trick();
+            while !cpu.is_instruction_boundary() {
+                cpu.trick();
+            }
+        }
+
+        #[test]
+        fn halt_bug() {
+            use crate::memory::Memory;
+            // HALT; INC A with an interrupt pending and IME=0
+            let mut cpu = flat(&[0x76, 0x3C]);
+            cpu.bus.set(0xFFFF, 0x01);
+            cpu.bus.set(0xFF0F, 0x01);
+            for _ in 0..3 {
+                step(&mut cpu);
+            }
+            assert!(!cpu.is_halted());
+            assert_eq!(cpu.registers().a, 0x03);
+            assert_eq!(cpu.registers().pc, 0x0102);
+        }
+
+        #[test]
+        fn ei_halt() {
+            use crate::memory::Memory;
+            // EI; HALT, the interrupt returns to the HALT
+            let mut cpu = flat(&[0xFB, 0x76]);
+            cpu.bus.set(0xFFFF, 0x01);
+            cpu.bus.set(0xFF0F, 0x01);
+            for _ in 0..3 {
+                step(&mut cpu);
+            }
+            let state = cpu.state();
+            assert_eq!(state.pc, 0x0040);
+            assert_eq!(cpu.bus.get_word(state.sp), 0x0101);
+        }
+
+        #[test]
+        fn halt_wake() {
+            use crate::memory::Memory;
+            // HALT; INC A, IME=0 wakes up without the interrupt being served
+            let mut cpu = flat(&[0x76, 0x3C]);
+            step(&mut cpu);
+            step(&mut cpu);
+            assert!(cpu.is_halted());
+            cpu.bus.set(0xFFFF, 0x04);
+            cpu.bus.set(0xFF0F, 0x04);
+            step(&mut cpu);
+            step(&mut cpu);
+            assert!(!cpu.is_halted());
+            assert_eq!(cpu.registers().a, 0x02);
+            assert_eq!(cpu.bus.get(0xFF0F), 0x04);
+        }
+
+        #[test]
+        fn stop_joypad() {
+            use crate::joypad::JoyPadKey;
+            // STOP; INC A
+            let mut gameboy = rom(&[0x10, 0x00, 0x3C], false);
+            for _ in 0..10000 {
+                gameboy.trick();
+            }
+            assert_eq!(gameboy.registers().pc, 0x0102);
+            assert_eq!(gameboy.registers().a, 0x01);
+            gameboy.input(JoyPadKey::Right, true);
+            for _ in 0..100 {
+                gameboy.trick();
+            }
+            assert_eq!(gameboy.registers().a, 0x02);
+        }
+
+        #[test]
+        fn speed_switch() {
+            // TIMA from 0 at 16 clocks a tick, TMA=80; LD A,1; LDH (KEY1),A; STOP; NOP; JR -2
+            let mut gameboy = rom(
+                &[
+                    0x3E, 0x05, 0xE0, 0x07, 0xAF, 0xE0, 0x05, 0x3E, 0x80, 0xE0, 0x06, 0x3E, 0x01,
+                    0xE0, 0x4D, 0x10, 0x00, 0x00, 0x18, 0xFE,
+                ],
+                true,
+            );
+            while gameboy.registers().pc < 0x0111 {
+                gameboy.trick();
+            }
+            assert!(gameboy.cpu_state().double_speed);
+            assert_eq!(gameboy.peek(0xFF4D) & 0x81, 0x80);
+            // neither DIV nor TIMA ran through the 2050 machine cycles of the switch
+            assert_eq!(gameboy.peek(0xFF04), 0x00);
+            assert!(gameboy.peek(0xFF05) < 0x10);
+        }
+
+        #[test]
+        fn halt_bug_timer() {
+            // DI; IE and TAC for a timer interrupt, TIMA=FF; 8 NOPs for it to overflow;
+            // HALT; INC B; JR -2
+            let mut code = vec![
+                0xF3, 0x3E, 0x04, 0xE0, 0xFF, 0xAF, 0xE0, 0x0F, 0x3E, 0x05, 0xE0, 0x07, 0x3E, 0xFF,
+                0xE0, 0x05,
+            ];
+            code.extend([0x00; 8]);
+            code.extend([0x76, 0x04, 0x18, 0xFE]);
+            let mut gameboy = rom(&code, false);
+            let b = gameboy.registers().b;
+            for _ in 0..1000 {
+                gameboy.trick();
+            }
+            // the byte after HALT runs twice and the interrupt stays pending
+            assert_eq!(gameboy.registers().pc, 0x011A);
+            assert_eq!(gameboy.registers().b, b.wrapping_add(2));
+            assert_eq!(gameboy.peek(0xFF0F) & 0x04, 0x04);
+        }
+    }
+
+    mod hdma {
+        #[test]
+        fn general_purpose() {
+            use crate::cpu::Cpu;
+            use crate::gameboy::GameBoy;
+            use crate::gameboy_mode::{GameBoyMode, Model};
+            use crate::memory::Memory;
+            use crate::mmu::Mmu;
+            // two blocks from C000 to 8000
+            let code = [
+                0x3E, 0xC0, 0xE0, 0x51, 0xAF, 0xE0, 0x52, 0xE0, 0x53, 0xE0, 0x54, 0x3E, 0x01,
+                0xE0, 0x55, 0x00,
+            ];
+            let mut rom = vec![0; 0x8000];
+            rom[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+            let mmu = Mmu::new(GameBoyMode::GBC, vec![], GameBoy::get_cartridge(rom));
+            let mut cpu = Cpu::new(Model::CGB, mmu, true);
+            for index in 0..0x20 {
+                cpu.bus.set(0xC000 + index, index as u8 + 1);
+            }
+            while cpu.registers().pc != 0x010F || !cpu.is_instruction_boundary() {
+                cpu.trick();
+            }
+            // the cpu is held for 8 machine cycles a block, one block after the other
+            for block in 0..2 {
+                let mut tricks = 0;
+                loop {
+                    cpu.trick();
+                    tricks += 1;
+                    if cpu.is_instruction_boundary() {
+                        break;
+                    }
+                }
+                assert_eq!(tricks, 8 * 4);
+                assert_eq!(cpu.registers().pc, 0x010F);
+                assert_eq!(cpu.bus.get(0x8000 + block * 0x10), block as u8 * 0x10 + 1);
+                if block == 0 {
+                    // one block left and the transfer still running
+                    assert_eq!(cpu.bus.get(0xFF55), 0x00);
+                    assert_eq!(cpu.bus.get(0x8010), 0x00);
+                }
+            }
+            assert_eq!(cpu.bus.get(0x801F), 0x20);
+            assert_eq!(cpu.bus.get(0xFF55), 0xFF);
+        }
+
+        #[test]
+        fn hblank_cancel() {
+            use crate::hdma::HDMA;
+            use crate::memory::Memory;
+            let mut hdma = HDMA::new();
+            hdma.set(0xFF53, 0x00);
+            hdma.set(0xFF54, 0x00);
+            hdma.set(0xFF55, 0x83);
+            assert_eq!(hdma.blocks_due(true), 1);
+            hdma.take_block();
+            // one block for every hblank
+            assert_eq!(hdma.blocks_due(true), 0);
+            assert_eq!(hdma.blocks_due(false), 0);
+            assert_eq!(hdma.blocks_due(true), 1);
+            hdma.take_block();
+            assert_eq!(hdma.get(0xFF55), 0x01);
+            hdma.set(0xFF55, 0x00);
+            assert_eq!(hdma.get(0xFF55), 0x81);
+            assert_eq!(hdma.blocks_due(false), 0);
+            assert_eq!(hdma.blocks_due(true), 0);
+        }
+
+        #[test]
+        fn end_of_vram() {
+            use crate::hdma::HDMA;
+            use crate::memory::Memory;
+            let mut hdma = HDMA::new();
+            hdma.set(0xFF51, 0xFF);
+            hdma.set(0xFF52, 0xF0);
+            hdma.set(0xFF53, 0xFF);
+            hdma.set(0xFF54, 0xF0);
+            hdma.set(0xFF55, 0x03);
+            assert_eq!(hdma.take_block(), (0xFFF0, 0x9FF0));
+            assert!(!hdma.active);
+            assert_eq!(hdma.get(0xFF55), 0xFF);
+            assert_eq!((hdma.source, hdma.destination), (0x0000, 0x0000));
+        }
+
+        #[test]
+        fn hblank_while_halted() {
+            // four blocks from 0100 to 8000 in hblank, then HALT with IE=0
+            let mut gameboy = super::halt::rom(
+                &[
+                    0x3E, 0x01, 0xE0, 0x51, 0xAF, 0xE0, 0x52, 0xE0, 0x53, 0xE0, 0x54, 0xE0, 0xFF,
+                    0x3E, 0x83, 0xE0, 0x55, 0x76,
+                ],
+                true,
+            );
+            gameboy.run_frame();
+            assert!(gameboy.is_halted());
+            assert_eq!(gameboy.peek(0xFF55), 0xFF);
+            for index in 0..0x40 {
+                assert_eq!(
+                    gameboy.peek(0x8000 + index),
+                    gameboy.peek(0x0100 + index)
+                );
+            }
+        }
+    }
+
+    mod memory_map {
+        #[test]
+        fn echo_ram() {
+            use crate::gameboy::GameBoy;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            use crate::mmu::Mmu;
+            let rom = vec![0; 0x8000];
+            let mut mmu = Mmu::new(GameBoyMode::GBC, vec![], GameBoy::get_cartridge(rom));
+            mmu.set(0xC010, 0x12);
+            assert_eq!(mmu.get(0xE010), 0x12);
+            // the upper half follows the selected WRAM bank
+            mmu.set(0xFF70, 0x03);
+            mmu.set(0xF020, 0x34);
+            assert_eq!(mmu.get(0xD020), 0x34);
+            mmu.set(0xFF70, 0x02);
+            assert_ne!(mmu.get(0xF020), 0x34);
+        }
+
+        #[test]
+        fn unusable() {
+            use crate::gameboy::GameBoy;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            use crate::mmu::Mmu;
+            let rom = vec![0; 0x8000];
+            let mut mmu = Mmu::new(GameBoyMode::GB, vec![], GameBoy::get_cartridge(rom.clone()));
+            mmu.set(0xFEA0, 0x12);
+            assert_eq!(mmu.get(0xFEA0), 0x00);
+            let mmu = Mmu::new(GameBoyMode::GBC, vec![], GameBoy::get_cartridge(rom));
+            assert_eq!(mmu.get(0xFEB4), 0xBB);
+        }
+
+        #[test]
+        fn boot_off_latch() {
+            use crate::gameboy::GameBoy;
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            use crate::mmu::Mmu;
+            let mut rom = vec![0; 0x8000];
+            rom[0x0000] = 0x42;
+            let mut mmu = Mmu::new(GameBoyMode::GB, vec![0x31; 0x100], GameBoy::get_cartridge(rom));
+            assert_eq!(mmu.get(0x0000), 0x31);
+            // only bit 0 turns the boot rom off
+            mmu.set(0xFF50, 0xFE);
+            assert_eq!(mmu.get(0x0000), 0x31);
+            mmu.set(0xFF50, 0x01);
+            assert_eq!(mmu.get(0x0000), 0x42);
+            mmu.set(0xFF50, 0x00);
+            assert_eq!(mmu.get(0x0000), 0x42);
+        }
+
+        #[test]
+        fn opri() {
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            use crate::ppu::PpuMmu;
+            // a DMG has no OPRI
+            let mut ppu = PpuMmu::new(GameBoyMode::GB);
+            ppu.set(0xFF6C, 0x00);
+            assert_eq!(ppu.get(0xFF6C), 0xFF);
+            let mut ppu = PpuMmu::new(GameBoyMode::GBC);
+            ppu.set(0xFF6C, 0x00);
+            assert_eq!(ppu.get(0xFF6C), 0xFE);
+            ppu.set(0xFF6C, 0x01);
+            assert_eq!(ppu.get(0xFF6C), 0xFF);
+        }
+    }
+
+    mod hooks {
+        #[test]
+        fn read_write_execute() {
+            use crate::hooks::{HookEvent, HookKind};
+            use std::cell::RefCell;
+            use std::rc::Rc;
+            // LD A,$12; LD ($C000),A; LD A,($C000); JR -2
+            let mut gameboy = super::halt::rom(
+                &[0x3E, 0x12, 0xEA, 0x00, 0xC0, 0xFA, 0x00, 0xC0, 0x18, 0xFE],
+                false,
+            );
+            let events = Rc::new(RefCell::new(vec![]));
+            for (kind, range) in [
+                (HookKind::Read, 0xC000..=0xC000),
+                (HookKind::Write, 0xC000..=0xDFFF),
+                (HookKind::Execute, 0x0100..=0x0107),
+            ] {
+                let events = events.clone();
+                gameboy.add_hook(kind, range, move |event| events.borrow_mut().push(*event));
+            }
+            for _ in 0..4 {
+                gameboy.trick();
+                while !gameboy.is_instruction_boundary() {
+                    gameboy.trick();
+                }
+            }
+            let event = |kind, address, value, bank| HookEvent {
+                kind,
+                address,
+                value,
+                bank,
+            };
+            assert_eq!(
+                *events.borrow(),
+                vec![
+                    event(HookKind::Execute, 0x0100, 0x3E, Some(0)),
+                    event(HookKind::Execute, 0x0102, 0xEA, Some(0)),
+                    event(HookKind::Write, 0xC000, 0x12, None),
+                    event(HookKind::Execute, 0x0105, 0xFA, Some(0)),
+                    event(HookKind::Read, 0xC000, 0x12, None),
+                ]
+            );
+        }
+
+        #[test]
+        fn load() {
+            use crate::gameboy::GameBoy;
+            use crate::hooks::HookKind;
+            use std::cell::Cell;
+            use std::rc::Rc;
+            // LD ($C000),A; JR -5
+            let code = [0xEA, 0x00, 0xC0, 0x18, 0xFB];
+            let mut gameboy = super::halt::rom(&code, false);
+            let writes = Rc::new(Cell::new(0));
+            let hook_writes = writes.clone();
+            let id = gameboy.add_hook(HookKind::Write, 0xC000..=0xC000, move |_| {
+                hook_writes.set(hook_writes.get() + 1)
+            });
+            let status = gameboy.save().unwrap();
+            let mut rom = vec![0; 0x8000];
+            rom[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+            let mut loaded = gameboy
+                .load(&status, GameBoy::get_cartridge(rom))
+                .unwrap();
+            // a loaded gameboy starts without hooks until they are moved over
+            assert!(!loaded.remove_hook(id));
+            loaded.set_hooks(gameboy.take_hooks());
+            assert!(!gameboy.remove_hook(id));
+            loaded.run_frame();
+            assert!(writes.get() > 0);
+            assert!(loaded.remove_hook(id));
+        }
+
+        #[test]
+        fn remove() {
+            use crate::hooks::{HookKind, Hooks};
+            let mut hooks = Hooks::default();
+            let id = hooks.add(HookKind::Write, 0x8000..=0x9FFF, |_| {});
+            assert!(!hooks.is_empty());
+            assert!(hooks.remove(id));
+            assert!(!hooks.remove(id));
+            assert!(hooks.is_empty());
+        }
+    }
+
+    mod peek {
+        #[test]
+        fn no_side_effects() {
+            use crate::hooks::HookKind;
+            use std::cell::Cell;
+            use std::rc::Rc;
+            let mut gameboy = super::halt::rom(&[0x18, 0xFE], false);
+            let reads = Rc::new(Cell::new(0));
+            let hook_reads = reads.clone();
+            gameboy.add_hook(HookKind::Read, 0x0000..=0xFFFF, move |_| {
+                hook_reads.set(hook_reads.get() + 1)
+            });
+            gameboy.poke(0xC000, 0x12);
+            assert_eq!(gameboy.peek(0xC000), 0x12);
+            assert_eq!(gameboy.peek(0xE000), 0x12);
+            // rom can't be poked and doesn't switch banks
+            gameboy.poke(0x0100, 0x00);
+            assert_eq!(gameboy.peek(0x0100), 0x18);
+            assert_eq!(reads.get(), 0);
+        }
+
+        #[test]
+        fn io_registers() {
+            let mut gameboy = super::halt::rom(&[0x18, 0xFE], true);
+            gameboy.poke(0xC000, 0x12);
+            // the registers keep the value without starting a DMA, logging or resetting DIV
+            gameboy.poke(0xFF46, 0xC0);
+            gameboy.poke(0xFF01, b'x');
+            gameboy.poke(0xFF02, 0x81);
+            gameboy.poke(0xFF04, 0x34);
+            gameboy.poke(0xFF55, 0x00);
+            assert_eq!(gameboy.peek(0xFF46), 0xC0);
+            assert_eq!(gameboy.peek(0xFF04), 0x34);
+            assert_eq!(gameboy.peek(0xFF55), 0xFF);
+            for _ in 0..200 {
+                gameboy.trick();
+            }
+            assert_eq!(gameboy.peek(0xFE00), 0x00);
+            assert!(gameboy.mmu().log_msg.is_empty());
+            assert!(gameboy.peek(0xFF04) >= 0x34);
+        }
+
+        #[test]
+        fn sound_and_stat() {
+            let mut gameboy = super::halt::rom(&[0x18, 0xFE], false);
+            gameboy.poke(0xFF12, 0xF0);
+            gameboy.poke(0xFF11, 0x3F);
+            let channel = gameboy.channel_state(0).unwrap();
+            let interrupts = gameboy.peek(0xFF0F);
+            // a trigger with the length enabled neither reloads the volume nor clocks the length
+            gameboy.poke(0xFF14, 0xC7);
+            assert_eq!(gameboy.peek(0xFF14) & 0x40, 0x40);
+            let after = gameboy.channel_state(0).unwrap();
+            assert_eq!(after.enable, channel.enable);
+            assert_eq!(after.volume, channel.volume);
+            assert_eq!(after.frequency & 0x0700, 0x0700);
+            // LYC matching LY with its interrupt enabled doesn't request it
+            let ly = gameboy.peek(0xFF44);
+            gameboy.poke(0xFF45, ly);
+            gameboy.poke(0xFF41, 0x40);
+            assert_eq!(gameboy.peek(0xFF41) & 0x44, 0x44);
+            assert_eq!(gameboy.peek(0xFF0F), interrupts);
+            gameboy.trick();
+            assert_eq!(gameboy.peek(0xFF0F), interrupts);
+        }
+
+        #[test]
+        fn banks() {
+            use crate::gameboy::GameBoy;
+            // MBC1 with 4 rom banks and 8KiB of ram
+            let mut rom = vec![0; 0x10000];
+            rom[0x0147] = 0x03;
+            rom[0x0149] = 0x02;
+            rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+            rom[2 * 0x4000 + 0x0010] = 0x77;
+            let mut gameboy = GameBoy::new(vec![], GameBoy::get_cartridge(rom));
+            assert_eq!(gameboy.peek_rom(2, 0x4010), 0x77);
+            assert_eq!(gameboy.peek(0x4010), 0x00);
+            assert_eq!(gameboy.peek_rom(4, 0x4010), 0xFF);
+            // the ram is still disabled
+            gameboy.poke(0xA010, 0x55);
+            assert_eq!(gameboy.peek_sram(0, 0xA010), 0x55);
+            assert_eq!(gameboy.peek(0xA010), 0xFF);
+        }
+
+        #[test]
+        fn gbc_banks() {
+            let mut gameboy = super::halt::rom(&[0x18, 0xFE], true);
+            gameboy.poke(0xFF70, 0x03);
+            gameboy.poke(0xD123, 0x42);
+            gameboy.poke(0xFF4F, 0x01);
+            gameboy.poke(0x8001, 0x99);
+            assert_eq!(gameboy.peek_wram(3, 0xD123), 0x42);
+            assert_eq!(gameboy.peek_wram(1, 0xD123), 0x00);
+            assert_eq!(gameboy.peek_vram(1, 0x8001), 0x99);
+            assert_eq!(gameboy.peek_vram(0, 0x8001), 0x00);
+        }
+    }
+
+    mod palette {
+        #[test]
+        fn parse() {
+            use crate::gameboy::DmgPalette;
+            assert_eq!("pocket".parse(), Ok(DmgPalette::POCKET));
+            assert_eq!(
+                "FFFFFF,AAAAAA,555555,000000".parse(),
+                Ok(DmgPalette::GRAYSCALE)
+            );
+            let palette: DmgPalette = "#E0F8D0,88C070,346856,081820,\
+                 FFFFFF,AAAAAA,555555,000000,\
+                 C4CFA1,8B956D,4D533C,1F1F1F"
+                .parse()
+                .unwrap();
+            assert_eq!(palette.bg, DmgPalette::GREEN.bg);
+            assert_eq!(palette.obj0, DmgPalette::GRAYSCALE.obj0);
+            assert_eq!(palette.obj1, DmgPalette::POCKET.obj1);
+            assert!("FFFFFF,AAAAAA".parse::<DmgPalette>().is_err());
+            assert!("blue".parse::<DmgPalette>().is_err());
+        }
+
+        #[test]
+        fn switch() {
+            use crate::gameboy::DmgPalette;
+            let mut gameboy = super::halt::rom(&[0x18, 0xFE], false);
+            let palette = DmgPalette::uniform([0x123456, 0, 0, 0]);
+            gameboy.set_dmg_palette(palette);
+            assert_eq!(gameboy.dmg_palette(), palette);
+            // the ® the boot rom leaves in the tile map shows as shade 0 too
+            gameboy.poke(0xFF47, 0x00);
+            gameboy.run_frame();
+            gameboy.run_frame();
+            assert!(gameboy.get_frame_buffer().iter().all(|&x| x == 0x123456));
+        }
+    }
+
+    mod raster {
+        #[test]
+        fn mid_line_write() {
+            use crate::gameboy_mode::GameBoyMode;
+            use crate::memory::Memory;
+            use crate::ppu::{PpuMmu, PPU, WIDTH};
+            let mut mmu = PpuMmu::new(GameBoyMode::GB);
+            let mut ppu = PPU::new(GameBoyMode::GB);
+            // tile 0 is all color 3, the objects use the blank tile 1
+            for index in 0x8000..0x8010 {
+                mmu.set(index, 0xFF);
+            }
+            // 10 objects at the left edge hold the fifo for 65 dots
+            for index in 0..10 {
+                let oam = 0xFE00 + index * 4;
+                mmu.set(oam, 16);
+                mmu.set(oam + 1, 8);
+                mmu.set(oam + 2, 1);
+            }
+            mmu.set(0xFF47, 0x00);
+            mmu.set(0xFF40, 0x93);
+            while mmu.get(0xFF44) != 1 || mmu.get(0xFF41) & 0x03 != 3 {
+                ppu.trick(&mut mmu, false);
+            }
+            // pixel 80 comes out 12 + 65 + 80 dots into the drawing, BGP is written just before
+            for _ in 0..156 {
+                ppu.trick(&mut mmu, false);
+            }
+            mmu.set(0xFF47, 0xFF);
+            while mmu.get(0xFF41) & 0x03 == 3 {
+                ppu.trick(&mut mmu, false);
+            }
+            let line = &ppu.frame_buffer[WIDTH..WIDTH * 2];
+            let palette = ppu.palette();
+            assert_eq!(line[79], palette.bg[0]);
+            assert_eq!(line[80], palette.bg[3]);
+        }
+    }
+
+    mod compat {
+        #[cfg(test)]
+        fn boot(keys: &[crate::joypad::JoyPadKey]) -> crate::gameboy::GameBoy {
+            use crate::gameboy::GameBoy;
+            use crate::util::read_rom;
+            let bios = read_rom("../tests/gbc_bios.bin").unwrap();
+            let rom = read_rom("../tests/Tetris.gb").unwrap();
+            let mut gameboy = GameBoy::new(bios, GameBoy::get_cartridge(rom));
+            for key in keys {
+                gameboy.input(key.clone(), true);
+            }
+            while gameboy.mmu().is_boot() {
+                gameboy.trick();
+            }
+            for key in keys {
+                gameboy.input(key.clone(), false);
+            }
+            gameboy
+        }
+        // the colors on screen with every BG pixel showing shade 1
+        #[cfg(test)]
+        fn shade1(gameboy: &mut crate::gameboy::GameBoy) -> Vec<u32> {
+            for _ in 0..30 {
+                gameboy.run_frame();
+            }
+            gameboy.poke(0xFF47, 0x55);
+            gameboy.run_frame();
+            gameboy.run_frame();
+            let mut colors = gameboy.get_frame_buffer().to_vec();
+            colors.dedup();
+            colors
+        }
+
+        #[test]
+        fn title_palette() {
+            use crate::gameboy_mode::GameBoyMode;
+            let mut gameboy = boot(&[]);
+            assert!(gameboy.mmu().mode == GameBoyMode::GBCCompat);
+            // OPRI keeps what the boot rom wrote, only the palette indexes still read
+            gameboy.poke(0xFF6C, 0x00);
+            assert_eq!(gameboy.peek(0xFF6C), 0xFF);
+            assert_eq!(gameboy.peek(0xFF68), 0xC8);
+            assert_eq!(gameboy.peek(0xFF69), 0xFF);
+            // the palette the boot rom looked up for the title of Tetris
+            assert_eq!(shade1(&mut gameboy), vec![0xFFFF00]);
+        }
+
+        #[test]
+        fn wram() {
+            use crate::gameboy::GameBoy;
+            use crate::util::read_rom;
+            let bios = read_rom("../tests/gbc_bios.bin").unwrap();
+            let rom = read_rom("../tests/Tetris.gb").unwrap();
+            let mut gameboy = GameBoy::new(bios, GameBoy::get_cartridge(rom));
+            gameboy.poke(0xFF70, 0x03);
+            gameboy.poke(0xD123, 0x42);
+            gameboy.poke(0xFF70, 0x01);
+            gameboy.poke(0xC123, 0x5A);
+            while gameboy.mmu().is_boot() {
+                gameboy.trick();
+            }
+            // what the boot rom saw is still there, bank 1 stays in and SVBK is gone
+            assert_eq!(gameboy.peek(0xC123), 0x5A);
+            gameboy.poke(0xFF70, 0x03);
+            assert_eq!(gameboy.peek(0xFF70), 0xFF);
+            assert_eq!(gameboy.peek(0xD123), 0x00);
+            gameboy.poke(0xD123, 0x24);
+            assert_eq!(gameboy.peek_wram(1, 0xD123), 0x24);
+            assert_eq!(gameboy.peek_wram(3, 0xD123), 0xFF);
+        }
+
+        #[test]
+        fn button_palette() {
+            use crate::joypad::JoyPadKey;
+            // left and B pick the grayscale one
+            let mut gameboy = boot(&[JoyPadKey::Left, JoyPadKey::B]);
+            assert_eq!(shade1(&mut gameboy), vec![0xA5A5A5]);
+        }
+
+        #[cfg(test)]
+        fn skip_boot() -> crate::gameboy::GameBoy {
+            use crate::gameboy::{GameBoy, Model};
+            use crate::util::read_rom;
+            let rom = read_rom("../tests/Tetris.gb").unwrap();
+            GameBoy::with_model(Model::CGB, vec![], GameBoy::get_cartridge(rom))
+        }
+
+        #[test]
+        fn title_palette_without_boot() {
+            // the same colors the boot rom picks in title_palette
+            let mut gameboy = skip_boot();
+            assert_eq!(shade1(&mut gameboy), vec![0xFFFF00]);
+        }
+
+        #[test]
+        fn button_palette_without_boot() {
+            use crate::joypad::JoyPadKey;
+            let mut gameboy = skip_boot();
+            assert!(!gameboy.select_compat_palette(&[JoyPadKey::Start]));
+            assert!(gameboy.select_compat_palette(&[JoyPadKey::Left, JoyPadKey::B]));
+            assert_eq!(shade1(&mut gameboy), vec![0xA5A5A5]);
+        }
+    }
+
+    mod model {
+        #[test]
+        fn registers() {
+            use crate::gameboy::{GameBoy, Model};
+            let mut rom = vec![0; 0x8000];
+            rom[0x014D] = 0x12;
+            let models = [
+                (Model::DMG0, 0x0100, 0xFF13),
+                (Model::DMG, 0x01B0, 0x0013),
+                (Model::MGB, 0xFFB0, 0x0013),
+                (Model::SGB, 0x0100, 0x0014),
+                (Model::SGB2, 0xFF00, 0x0014),
+                (Model::CGB, 0x1180, 0x0000),
+                (Model::AGB, 0x1100, 0x0100),
+            ];
+            for (model, af, bc) in models {
+                let gameboy =
+                    GameBoy::with_model(model, vec![], GameBoy::get_cartridge(rom.clone()));
+                let registers = gameboy.registers();
+                assert_eq!(gameboy.model(), model);
+                assert_eq!(
+                    (registers.get_af(), registers.get_bc()),
+                    (af, bc),
+                    "{:?}",
+                    model
+                );
+                assert_eq!((registers.sp, registers.pc), (0xFFFE, 0x0100));
+            }
+            // a GBC cartridge keeps the GBC registers, a DMG runs it as a GB game
+            rom[0x0143] = 0x80;
+            let gameboy =
+                GameBoy::with_model(Model::CGB, vec![], GameBoy::get_cartridge(rom.clone()));
+            assert_eq!(gameboy.registers().get_de(), 0xFF56);
+            let gameboy = GameBoy::with_model(Model::DMG, vec![], GameBoy::get_cartridge(rom));
+            assert!(gameboy.mmu().mode == crate::gameboy_mode::GameBoyMode::GB);
+        }
+
+        #[test]
+        fn compat_without_boot() {
+            use crate::gameboy::{GameBoy, Model};
+            use crate::gameboy_mode::GameBoyMode;
+            // BGP shows shade 1 for everything and the LCD goes on
+            let code = [0x3E, 0x55, 0xE0, 0x47, 0x3E, 0x91, 0xE0, 0x40, 0x18, 0xFE];
+            let mut rom = vec![0; 0x8000];
+            rom[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+            let mut gameboy = GameBoy::with_model(Model::CGB, vec![], GameBoy::get_cartridge(rom));
+            assert!(gameboy.mmu().mode == GameBoyMode::GBCCompat);
+            assert_eq!(gameboy.registers().get_hl(), 0x007C);
+            gameboy.run_frame();
+            gameboy.run_frame();
+            // not a Nintendo game, so the boot rom's default green palette
+            assert!(gameboy.get_frame_buffer().iter().all(|&x| x == 0x7BFF31));
+        }
+    }
+
+    mod lockup {
+        #[test]
+        fn illegal_opcode() {
+            use crate::gameboy::Lockup;
+            // INC A; DB $D3; INC A
+            let mut gameboy = super::halt::rom(&[0x3C, 0xD3, 0x3C], false);
+            let lockup = Lockup {
+                opcode: 0xD3,
+                address: 0x0101,
+            };
+            for _ in 0..3 {
+                gameboy.run_frame();
+            }
+            assert_eq!(gameboy.lockup(), Some(lockup));
+            assert_eq!(gameboy.take_lockup(), Some(lockup));
+            assert_eq!(gameboy.take_lockup(), None);
+            assert_eq!(gameboy.registers().a, 0x02);
+        }
+
+        #[test]
+        fn debugger_break() {
+            use crate::debugger::{Debugger, StopReason};
+            use crate::gameboy::Lockup;
+            let mut gameboy = super::halt::rom(&[0x00, 0xFD], false);
+            let mut debugger = Debugger::new();
+            debugger.set_break_on_lockup(true);
+            let lockup = Lockup {
+                opcode: 0xFD,
+                address: 0x0101,
+            };
+            assert_eq!(
+                debugger.run_to_frame(&mut gameboy),
+                StopReason::Lockup(lockup)
+            );
+            assert_eq!(gameboy.take_lockup(), None);
+            assert_eq!(debugger.run_to_frame(&mut gameboy), StopReason::Frame);
+        }
+    }
+
+    mod sm83 {
+        // Runs every case of a SingleStepTests sm83 json file on the cpu with a flat 64KiB bus,
+        // every access has to happen in the same machine cycle as on the hardware.
+        #[cfg(test)]
+        fn run_file(path: &std::path::Path) -> Vec<String> {
+            use crate::bus::{FlatBus, RecordingBus};
+            use crate::cpu::{Cpu, CpuState};
+            use crate::debugger::Access;
+            use crate::gameboy_mode::Model;
+            use crate::memory::Memory;
+            use serde_json::Value;
+            use std::panic::{catch_unwind, AssertUnwindSafe};
+
+            fn word(state: &Value, key: &str) -> u16 {
+                state[key].as_u64().unwrap_or(0) as u16
+            }
+            fn pair(state: &Value, high: &str, low: &str) -> u16 {
+                word(state, high) << 8 | word(state, low)
+            }
+            fn ram(state: &Value) -> Vec<(u16, u8)> {
+                let entries = state["ram"].as_array().cloned().unwrap_or_default();
+                entries
+                    .iter()
+                    .map(|entry| {
+                        (
+                            entry[0].as_u64().unwrap() as u16,
+                            entry[1].as_u64().unwrap() as u8,
+                        )
+                    })
+                    .collect()
+            }
+            fn accesses(cycles: &Value) -> Vec<(u32, u16, u8, Access)> {
+                let cycles = cycles.as_array().cloned().unwrap_or_default();
+                cycles
+                    .iter()
+                    .zip(1..)
+                    .filter_map(|(cycle, tick)| {
+                        let address = cycle[0].as_u64()? as u16;
+                        let value = cycle[1].as_u64()? as u8;
+                        let kind = cycle[2].as_str()?;
+                        if kind.starts_with('r') {
+                            Some((tick, address, value, Access::Read))
+                        } else if kind.contains('w') {
+                            Some((tick, address, value, Access::Write))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect()
+            }
+
+            let text = std::fs::read_to_string(path).unwrap();
+            let cases: Vec<Value> = serde_json::from_str(&text).unwrap();
+            let mut failures = vec![];
+            for case in cases.iter() {
+                let name = case["name"].as_str().unwrap_or("").to_owned();
+                let initial = &case["initial"];
+                let expect = &case["final"];
+                let bus = RecordingBus::new(FlatBus::new());
+                let mut cpu = Cpu::new(Model::DMG, bus, true);
+                for (address, value) in ram(initial) {
+                    cpu.bus.inner.set(address, value);
+                }
+                if initial.get("ie").is_some() {
+                    cpu.bus.inner.set(0xFFFF, word(initial, "ie") as u8);
+                }
+                cpu.set_state(CpuState {
+                    af: pair(initial, "a", "f"),
+                    bc: pair(initial, "b", "c"),
+                    de: pair(initial, "d", "e"),
+                    hl: pair(initial, "h", "l"),
+                    sp: word(initial, "sp"),
+                    pc: word(initial, "pc"),
+                    ime: word(initial, "ime") != 0,
+                    ime_next: if word(initial, "ei") != 0 {
+                        Some(true)
+                    } else {
+                        None
+                    },
+                    halted: false,
+                    double_speed: false,
+                });
+
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    let mut ticks = 0;
+                    loop {
+                        cpu.trick();
+                        ticks += 1;
+                        if cpu.is_instruction_boundary() {
+                            break ticks;
+                        }
+                    }
+                }));
+                let ticks = match result {
+                    Ok(ticks) => ticks,
+                    Err(_) => {
+                        failures.push(format!("{}: panicked", name));
+                        continue;
+                    }
+                };
+
+                let state = cpu.state();
+                let registers = [
+                    ("AF", state.af, pair(expect, "a", "f")),
+                    ("BC", state.bc, pair(expect, "b", "c")),
+                    ("DE", state.de, pair(expect, "d", "e")),
+                    ("HL", state.hl, pair(expect, "h", "l")),
+                    ("SP", state.sp, word(expect, "sp")),
+                    ("PC", state.pc, word(expect, "pc")),
+                ];
+                for (register, value, expect) in registers {
+                    if value != expect {
+                        failures.push(format!(
+                            "{}: {} is {:04X}, expect {:04X}",
+                            name, register, value, expect
+                        ));
+                    }
+                }
+                if expect.get("ime").is_some() && state.ime != (word(expect, "ime") != 0) {
+                    failures.push(format!("{}: IME is {}", name, state.ime));
+                }
+                for (address, expect) in ram(expect) {
+                    let value = cpu.bus.inner.get(address);
+                    if value != expect {
+                        failures.push(format!(
+                            "{}: ({:04X}) is {:02X}, expect {:02X}",
+                            name, address, value, expect
+                        ));
+                    }
+                }
+                let cycles = case["cycles"].as_array().map(|c| c.len()).unwrap_or(0);
+                if ticks / 4 != cycles {
+                    failures.push(format!("{}: {} cycles, expect {}", name, ticks / 4, cycles));
+                }
+                // the json doesn't tell opcode fetches apart from reads
+                let activity: Vec<_> = cpu
+                    .bus
+                    .take()
+                    .into_iter()
+                    .map(|(tick, address, value, access)| match access {
+                        Access::Fetch => (tick, address, value, Access::Read),
+                        access => (tick, address, value, access),
+                    })
+                    .collect();
+                let expect = accesses(&case["cycles"]);
+                if activity != expect {
+                    failures.push(format!(
+                        "{}: bus activity {:X?}, expect {:X?}",
+                        name, activity, expect
+                    ));
+                }
+            }
+            failures
+        }
+
+        // a few cases in the corpus format, with the wrap-arounds and IME changes it checks
+        #[test]
+        fn sample() {
+            let failures = run_file(std::path::Path::new("tests/sm83-sample/sample.json"));
+            assert!(failures.is_empty(), "{:#?}", failures);
+        }
+
+        // every opcode from random registers and memory, like the corpus has them, mustn't panic
+        #[test]
+        fn random_states() {
+            use crate::bus::FlatBus;
+            use crate::cpu::{Cpu, CpuState};
+            use crate::gameboy_mode::Model;
+            use crate::memory::Memory;
+            use std::panic::{catch_unwind, AssertUnwindSafe};
+            let mut seed = 0x2545F491u32;
+            let mut random = move || {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed
+            };
+            // one in four right below the end of memory, where the overflows are
+            let mut word = move || match random() {
+                value if value % 4 == 0 => 0xFFFF - (value >> 8) as u16 % 4,
+                value => value as u16,
+            };
+            let mut panics = vec![];
+            for opcode in 0..=0xFFu8 {
+                // STOP and HALT wait for the outside
+                if opcode == 0x10 || opcode == 0x76 {
+                    continue;
+                }
+                let mut cpu = Cpu::new(Model::DMG, FlatBus::new(), true);
+                for address in 0..=0xFFFF {
+                    cpu.bus.set(address, word() as u8);
+                }
+                for _ in 0..64 {
+                    let pc = word();
+                    cpu.bus.set(pc, opcode);
+                    cpu.set_state(CpuState {
+                        af: word() & 0xFFF0,
+                        bc: word(),
+                        de: word(),
+                        hl: word(),
+                        sp: word(),
+                        pc,
+                        ime: false,
+                        ime_next: None,
+                        halted: false,
+                        double_speed: false,
+                    });
+                    let result = catch_unwind(AssertUnwindSafe(|| loop {
+                        cpu.trick();
+                        if cpu.is_instruction_boundary() {
+                            break;
+                        }
+                    }));
+                    if result.is_err() {
+                        panics.push(format!("{:02X} at {:04X}", opcode, pc));
+                        break;
+                    }
+                }
+            }
+            assert!(panics.is_empty(), "{:#?}", panics);
+        }
+
+        // Put the json files of https://github.com/SingleStepTests/sm83 in tests/sm83
+        // or point SM83_TESTS to them, then run with --ignored.
+        #[test]
+        #[ignore = "needs the SingleStepTests sm83 json files"]
+        fn corpus() {
+            use std::path::PathBuf;
+            let dir = std::env::var("SM83_TESTS").unwrap_or("tests/sm83".to_owned());
+            let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+                .unwrap_or_else(|error| panic!("{}: {}", dir, error))
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|e| e == "json"))
+                .collect();
+            assert!(!paths.is_empty(), "no json files in {}", dir);
+            paths.sort();
+            let mut failures = vec![];
+            for path in paths {
+                failures.extend(run_file(&path));
+            }
+            let count = failures.len();
+            failures.truncate(50);
+            assert_eq!(count, 0, "{:#?}", failures);
+        }
+    }
+
+    mod audio {
+        // compares a digest of the recorded wav, dump it with `rust_gameboy record-audio` to listen
+        #[test]
+        fn tetris() {
+            use crate::gameboy::GameBoy;
+            use crate::joypad::JoyPadKey;
+            use crate::util::read_rom;
+            use crate::wav::WavWriter;
+            use std::io::Cursor;
+
+            let rom = read_rom("../tests/Tetris.gb").unwrap();
+            let cartridge = GameBoy::get_cartridge(rom);
+            let mut gameboy = GameBoy::new(vec![], cartridge);
+            gameboy.set_sample_rate(22050);
+            let mut writer = WavWriter::new(Cursor::new(vec![]), 22050).unwrap();
+            let mut peaks = vec![];
+            for frame in 0..600 {
+                match frame {
+                    250 | 400 => gameboy.input(JoyPadKey::Start, true),
+                    255 | 405 => gameboy.input(JoyPadKey::Start, false),
+                    _ => {}
+                }
+                gameboy.run_frame();
+                let samples = gameboy.take_audio_samples_i16();
+                peaks.push(samples.iter().map(|sample| sample.unsigned_abs()).max());
+                writer.write_samples(&samples).unwrap();
+            }
+            // the copyright and title screens are quiet, the music starts with the game type menu
+            assert!(peaks[20..400].iter().all(|&peak| peak == Some(0)));
+            let loud = peaks[410..].iter().filter(|&&peak| peak > Some(8000)).count();
+            assert!(loud > 120);
+            let wav = writer.finish().unwrap().into_inner();
+            assert_eq!(&wav[0..4], b"RIFF");
+            assert_eq!(
+                u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]) as usize,
+                wav.len() - 44
+            );
+            // FNV-1a
+            let digest = wav.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+            });
+            let digest = format!("{:016x}", digest);
+            // UPDATE_AUDIO_EXPECT=1 writes the digest for a change that is meant to alter the sound
+            if std::env::var_os("UPDATE_AUDIO_EXPECT").is_some() {
+                std::fs::write("tests/audio/expect", &digest).unwrap();
+            }
+            let expect = std::fs::read_to_string("tests/audio/expect").unwrap();
+            assert_eq!(digest, expect.trim());
+        }
+    }
 }
@@ -0,0 +1,116 @@
+use crate::memory::Memory;
+
+// TIMA counts the falling edges of one bit of the 16-bit system counter, DIV is its high byte
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed in the last machine cycle and reads 0 until it's reloaded
+    overflow: bool,
+    // TIMA got reloaded in this machine cycle, writes to it are ignored and TMA goes through
+    reloading: bool,
+    pub interrupt_flag: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloading: false,
+            interrupt_flag: false,
+        }
+    }
+    // one machine cycle, the counter runs on the cpu clock so double speed doubles it too
+    pub fn tick(&mut self) {
+        self.reloading = false;
+        if self.overflow {
+            self.overflow = false;
+            self.tima = self.tma;
+            self.interrupt_flag = true;
+            self.reloading = true;
+        }
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if signal && !self.signal() {
+            self.increase();
+        }
+    }
+    // a register as it would read afterwards, without resetting the counter or moving TIMA
+    pub(crate) fn store(&mut self, index: u16, value: u8) {
+        match index {
+            0xFF04 => self.counter = self.counter & 0x00FF | (value as u16) << 8,
+            0xFF05 => self.tima = value,
+            0xFF06 => self.tma = value,
+            0xFF07 => self.tac = value & 0x07,
+            _ => panic!("timer doesn't have {:04X}", index),
+        }
+    }
+    // the boot rom hands over with the counter running
+    pub(crate) fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+    // the bit of the counter TIMA follows, masked by the enable bit
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+    fn increase(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflow = overflow;
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory for Timer {
+    fn get(&self, index: u16) -> u8 {
+        match index {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => panic!("timer doesn't have {:04X}", index),
+        }
+    }
+    // every write can make the selected bit fall and increase TIMA
+    fn set(&mut self, index: u16, value: u8) {
+        let signal = self.signal();
+        match index {
+            0xFF04 => self.counter = 0,
+            0xFF05 => {
+                if !self.reloading {
+                    self.tima = value;
+                    // a write in the cycle after the overflow cancels the reload
+                    self.overflow = false;
+                }
+            }
+            0xFF06 => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            }
+            0xFF07 => self.tac = value & 0x07,
+            _ => panic!("timer doesn't have {:04X}", index),
+        }
+        if signal && !self.signal() {
+            self.increase();
+        }
+    }
+}